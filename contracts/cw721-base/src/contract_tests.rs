@@ -1,16 +1,17 @@
 #![cfg(test)]
 use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-use cosmwasm_std::{ DepsMut, Empty, Decimal};
+use cosmwasm_std::{ DepsMut, Empty, Decimal, Uint128};
 
 use cw721::{
-    ContractInfoResponse, Cw721Query, CollectionInfo, Royalty
+    CheckRoyaltiesResponse, ContractInfoResponse, Cw721Query, CollectionInfo,
+    RoyaltiesInfoResponse, Royalty
 };
 
 use crate::{
-    Cw721Contract, Extension, InstantiateMsg
+    Cw721Contract, ExecuteMsg, Extension, InstantiateMsg, Metadata, MintMsg
 };
 
-const MINTER: &str = "merlin";
+const MINTER: &str = "minter";
 const CONTRACT_NAME: &str = "Magic Power";
 const SYMBOL: &str = "MGK";
 const ADMIN: &str = "admin";
@@ -93,3 +94,57 @@ fn proper_instantiation() {
     //let tokens = contract.all_tokens(deps.as_ref(), None, None).unwrap();
     // assert_eq!(0, tokens.tokens.len());
 }
+
+#[test]
+fn query_royalty_info() {
+    let mut deps = mock_dependencies();
+    let contract = setup_contract(deps.as_mut());
+
+    let mint_msg = ExecuteMsg::Mint(MintMsg::<Extension> {
+        token_id: "petrify".to_string(),
+        owner: "medusa".to_string(),
+        token_uri: None,
+        content_type: "ai_nft".to_string(),
+        extension: Metadata {
+            image_url: "image".to_string(),
+            minter: MINTER.to_string(),
+        },
+    });
+    let minter = mock_info(MINTER, &[]);
+    contract
+        .execute(deps.as_mut(), mock_env(), minter, mint_msg)
+        .unwrap();
+
+    let res = contract
+        .royalty_info(deps.as_ref(), "petrify".to_string(), Uint128::new(1000))
+        .unwrap();
+    assert_eq!(
+        res,
+        RoyaltiesInfoResponse {
+            address: "owner".to_string(),
+            royalty_amount: Uint128::new(100),
+        }
+    );
+
+    // unknown tokens have no royalty
+    contract
+        .royalty_info(deps.as_ref(), "unknown".to_string(), Uint128::new(1000))
+        .unwrap_err();
+
+    let res = contract.check_royalties(deps.as_ref()).unwrap();
+    assert_eq!(res, CheckRoyaltiesResponse { royalty_payments: true });
+
+    // a zero rate means no royalty payments
+    let admin = mock_info(ADMIN, &[]);
+    let change_msg = ExecuteMsg::ChangeRoyalty {
+        royalty: Royalty {
+            address: "owner".to_string(),
+            royalty_rate: Decimal::zero(),
+        },
+    };
+    contract
+        .execute(deps.as_mut(), mock_env(), admin, change_msg)
+        .unwrap();
+    let res = contract.check_royalties(deps.as_ref()).unwrap();
+    assert_eq!(res, CheckRoyaltiesResponse { royalty_payments: false });
+}
@@ -1,8 +1,11 @@
 use crate::{ExecuteMsg, QueryMsg};
-use cosmwasm_std::{to_binary, Addr, CosmosMsg, QuerierWrapper, StdResult, WasmMsg, WasmQuery};
+use cosmwasm_std::{
+    to_binary, Addr, CosmosMsg, QuerierWrapper, StdResult, Uint128, WasmMsg, WasmQuery,
+};
 use cw721::{
-    AllNftInfoResponse, Approval, ApprovalResponse, ApprovalsResponse, ContractInfoResponse,
-    NftInfoResponse, NumTokensResponse, OperatorsResponse, OwnerOfResponse, TokensResponse,
+    AllNftInfoResponse, Approval, ApprovalResponse, ApprovalsResponse, CheckRoyaltiesResponse,
+    ContractInfoResponse, NftInfoResponse, NumTokensResponse, OperatorsResponse, OwnerOfResponse,
+    RoyaltiesInfoResponse, TokensResponse,
 };
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
@@ -156,4 +159,24 @@ impl Cw721Contract {
         self.query(querier, req)
     }
 
+    /// With royalty extension
+    pub fn royalty_info<T: Into<String>>(
+        &self,
+        querier: &QuerierWrapper,
+        token_id: T,
+        sale_price: Uint128,
+    ) -> StdResult<RoyaltiesInfoResponse> {
+        let req = QueryMsg::RoyaltyInfo {
+            token_id: token_id.into(),
+            sale_price,
+        };
+        self.query(querier, req)
+    }
+
+    /// With royalty extension
+    pub fn check_royalties(&self, querier: &QuerierWrapper) -> StdResult<bool> {
+        let req = QueryMsg::CheckRoyalties {};
+        let res: CheckRoyaltiesResponse = self.query(querier, req)?;
+        Ok(res.royalty_payments)
+    }
 }
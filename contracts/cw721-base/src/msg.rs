@@ -1,7 +1,7 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::Binary;
+use cosmwasm_std::{Binary, Uint128};
 use cw721::{Expiration,CollectionInfo,MintInfo,Royalty};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -152,7 +152,18 @@ pub enum QueryMsg {
     // Return the minter
     Minter {},
     Admin {},
-    GetCollectionState{}
+    GetCollectionState{},
+    /// With Royalty extension.
+    /// Returns the royalty owed to the collection creator when `token_id`
+    /// is sold for `sale_price`.
+    /// Return type: `RoyaltiesInfoResponse`
+    RoyaltyInfo {
+        token_id: String,
+        sale_price: Uint128,
+    },
+    /// With Royalty extension.
+    /// Return type: `CheckRoyaltiesResponse`
+    CheckRoyalties {},
 }
 
 /// Shows who can mint these tokens
@@ -1,12 +1,12 @@
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use cosmwasm_std::{to_binary, Addr, Binary, BlockInfo, Deps, Env, Order, StdError, StdResult};
+use cosmwasm_std::{to_binary, Addr, Binary, BlockInfo, Deps, Env, Order, StdError, StdResult, Uint128};
 
 use cw721::{
     AllNftInfoResponse, ApprovalResponse, ApprovalsResponse, ContractInfoResponse, CustomMsg,
     Cw721Query, Expiration, NftInfoResponse, NumTokensResponse, OperatorsResponse, OwnerOfResponse,
-    TokensResponse,TokensInfo,CollectionInfoResponse
+    TokensResponse,TokensInfo,CollectionInfoResponse,RoyaltiesInfoResponse,CheckRoyaltiesResponse
 };
 use cw_storage_plus::Bound;
 use cw_utils::maybe_addr;
@@ -222,6 +222,29 @@ where
             },
         })
     }
+
+    /// Royalty is the same for every token in the collection, so `token_id` is only
+    /// checked for existence.
+    fn royalty_info(
+        &self,
+        deps: Deps,
+        token_id: String,
+        sale_price: Uint128,
+    ) -> StdResult<RoyaltiesInfoResponse> {
+        self.tokens.load(deps.storage, &token_id)?;
+        let royalty = self.royalty_info.load(deps.storage)?;
+        Ok(RoyaltiesInfoResponse {
+            address: royalty.address,
+            royalty_amount: sale_price * royalty.royalty_rate,
+        })
+    }
+
+    fn check_royalties(&self, deps: Deps) -> StdResult<CheckRoyaltiesResponse> {
+        let royalty = self.royalty_info.load(deps.storage)?;
+        Ok(CheckRoyaltiesResponse {
+            royalty_payments: !royalty.royalty_rate.is_zero(),
+        })
+    }
 }
 
 impl<'a, T, C> Cw721Contract<'a, T, C>
@@ -248,6 +271,11 @@ where
             QueryMsg::Minter {} => to_binary(&self.minter(deps)?),
             QueryMsg::Admin {} => to_binary(&self.admin(deps)?),
             QueryMsg::GetCollectionState {  }  => to_binary(&self.get_colletion_state(deps)?),
+            QueryMsg::RoyaltyInfo {
+                token_id,
+                sale_price,
+            } => to_binary(&self.royalty_info(deps, token_id, sale_price)?),
+            QueryMsg::CheckRoyalties {} => to_binary(&self.check_royalties(deps)?),
             QueryMsg::ContractInfo {} => to_binary(&self.contract_info(deps)?),
             QueryMsg::NftInfo { token_id } => to_binary(&self.nft_info(deps, token_id)?),
             QueryMsg::OwnerOf {
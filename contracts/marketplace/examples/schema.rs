@@ -4,10 +4,10 @@ use std::fs::create_dir_all;
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
 use human_market_place::msg::{
-     ExecuteMsg, FeeResponse, InstantiateMsg, Offer, OffersResponse, QueryMsg,
-    SellNft,
+     CollectionStatsResponse, Cw20HookMsg, DenomResponse, ExecuteMsg, FeeResponse, InstantiateMsg, MigrateMsg, Offer, OffersResponse,
+    QueryMsg, ReceiveNftMsg, SellNft,
 };
-use human_market_place::state::State;
+use human_market_place::state::{AuctionResult, BuyOfferResult, SaleResult, State, TradeResult};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -16,10 +16,19 @@ fn main() {
     remove_schemas(&out_dir).unwrap();
 
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
     export_schema(&schema_for!(State), &out_dir);
     export_schema(&schema_for!(SellNft), &out_dir);
+    export_schema(&schema_for!(ReceiveNftMsg), &out_dir);
+    export_schema(&schema_for!(Cw20HookMsg), &out_dir);
+    export_schema(&schema_for!(DenomResponse), &out_dir);
+    export_schema(&schema_for!(CollectionStatsResponse), &out_dir);
+    export_schema(&schema_for!(AuctionResult), &out_dir);
+    export_schema(&schema_for!(BuyOfferResult), &out_dir);
+    export_schema(&schema_for!(TradeResult), &out_dir);
+    export_schema(&schema_for!(SaleResult), &out_dir);
     export_schema(&schema_for!(FeeResponse), &out_dir);
     export_schema(&schema_for!(Offer), &out_dir);
     export_schema(&schema_for!(OffersResponse), &out_dir);
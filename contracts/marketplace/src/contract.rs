@@ -1,22 +1,60 @@
 use cosmwasm_std::{
-    coin, entry_point, from_binary, to_binary, BankMsg, Binary, CosmosMsg, Decimal, Deps, DepsMut,
-    Env, MessageInfo,  Response, StdResult, Uint128, WasmMsg,
+    coin, entry_point, from_binary, to_binary, Addr, BankMsg, Binary, Coin, CosmosMsg, Decimal,
+    BlockInfo, Deps, DepsMut, Env, Event, MessageInfo, Order, Response, StdError, StdResult,
+    Storage, Uint128, WasmMsg,
 };
 
-use crate::cw721::{Cw721ExecuteMsg, Cw721ReceiveMsg};
+use crate::cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
+use crate::cw721::{
+    Cw721ExecuteMsg, Cw721QueryMsg, Cw721ReceiveMsg, OwnerOfResponse, RoyaltiesInfoResponse,
+};
 use crate::error::ContractError;
 use crate::msg::{
-    ExecuteMsg, FeeResponse, InstantiateMsg,  QueryMsg,
-    SellNft,
+    CollectionStatsResponse, Cw20HookMsg, DenomResponse, ExecuteMsg, FactoryQueryMsg, FeeResponse, InstantiateMsg, MigrateMsg, QueryMsg,
+    ListItem, ReceiveNftMsg, SellNft, StartAuction,
+};
+use crate::state::{
+    add_coin, buy_offers, AdminInfo, ACCRUED_FEES, FEE_RECIPIENTS, decrement_offerings, get_fund, increment_offerings, next_auction_id,
+    next_buy_offer_id, next_offering_id, offerings, Auction, AuctionResult, Bid, BuyOffer,
+    BuyOfferResult, CollectionInfo, Custody, DenomInfo, DenomKind, Offering, OfferingResult,
+    maybe_addr, next_sale_id, FeeTier, PendingFee, COLLECTION_FEES, FEE_TIERS, SELLER_VOLUME, sales, DenomVolume, SaleHistoryInfo, SaleResult, State, AUCTIONS, COLLECTIONINFO, COLLECTION_ACCESS,
+    ROYALTY_COLLECTIONS,
+    DENOMS, SALE_STATS, STATE, TRADERS, VOLUME_BUCKETS, VOLUME_BUCKET_SECONDS,
+};
+use crate::trade::{
+    execute_accept_trade, execute_cancel_trade, execute_propose_trade, query_trade,
+    query_trades_by_counterparty, query_trades_by_proposer,
+};
+use cw_storage_plus::{Bound, MultiIndex};
+use cw_utils::Expiration;
+use crate::migrations::{
+    migrate_batch_v0_1, migrate_from_v0_1, DEFAULT_BATCH, MAX_BATCH, MIGRATION,
 };
-use crate::state::{get_fund, increment_offerings, Offering, State, OFFERINGS, STATE, CollectionInfo, COLLECTIONINFO, SALEHISTORY,SaleHistoryInfo, self, OfferingResult};
-use cw2::set_contract_version;
-use std::ops::{Mul, Sub};
+use cw2::{get_contract_version, set_contract_version};
+use semver::Version;
+use std::convert::TryFrom;
 
 // version info for migration info
 const CONTRACT_NAME: &str = "human_market_place";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+pub(crate) const DEFAULT_LIMIT: u32 = 10;
+pub(crate) const MAX_LIMIT: u32 = 30;
+
+/// Listings of each denom `CollectionStats` looks at, cheapest first, to find
+/// the floor price.
+const FLOOR_SCAN_LIMIT: usize = 100;
+
+/// Listings `PruneExpired` reads per call, expired or not.
+const PRUNE_SCAN_LIMIT: usize = 100;
+
+/// Longest notice the fee timelock can give, 30 days.
+const MAX_FEE_TIMELOCK: u64 = 30 * 86400;
+
+/// A bid landing within this many seconds of the auction end pushes the end
+/// back to this many seconds after the bid.
+const AUCTION_EXTENSION_WINDOW: u64 = 600;
+
 
 #[entry_point]
 pub fn instantiate(
@@ -27,17 +65,66 @@ pub fn instantiate(
 ) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
+    check_fee(msg.fee, msg.max_fee)?;
+    check_fee_timelock(msg.fee_timelock)?;
     let state = State {
         fee: msg.fee,
-        owner: info.sender,
-        tvl: Uint128::zero(),
-        denom:msg.denom
+        max_fee: msg.max_fee,
+        fee_timelock: msg.fee_timelock,
+        pending_fee: None,
+        owner: info.sender.clone(),
+        tvl: vec![],
+        factory: maybe_addr(deps.api, msg.factory)?,
+        pending_owner: None,
+        fee_manager: info.sender.clone(),
+        pauser: info.sender,
+        paused: false,
     };
     STATE.save(deps.storage, &state)?;
+    DENOMS.save(
+        deps.storage,
+        &msg.denom,
+        &DenomInfo {
+            kind: DenomKind::Native,
+            accepted: true,
+        },
+    )?;
 
     Ok(Response::default())
 }
 
+/// Runs the storage migrations of every release between the stored version and
+/// this one. Downgrades and other contracts are refused.
+#[entry_point]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let stored = get_contract_version(deps.storage)?;
+    if stored.contract != CONTRACT_NAME {
+        return Err(ContractError::CannotMigrate {
+            previous_contract: stored.contract,
+        });
+    }
+    let previous = parse_version(&stored.version)?;
+    if previous > parse_version(CONTRACT_VERSION)? {
+        return Err(ContractError::CannotMigrateVersion {
+            previous_version: stored.version,
+        });
+    }
+
+    if previous < Version::new(0, 2, 0) {
+        migrate_from_v0_1(deps.storage)?;
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("from_version", stored.version)
+        .add_attribute("to_version", CONTRACT_VERSION))
+}
+
+fn parse_version(version: &str) -> StdResult<Version> {
+    version.parse().map_err(|err: semver::Error| StdError::generic_err(err.to_string()))
+}
+
 // And declare a custom Error variant for the ones where you will want to make use of it
 #[entry_point]
 pub fn execute(
@@ -46,213 +133,477 @@ pub fn execute(
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+    if is_trading(&msg) && STATE.load(deps.storage)?.paused {
+        return Err(ContractError::Paused {});
+    }
+
     match msg {
-        ExecuteMsg::Buy { offering_id,address } => execute_buy(deps,env, info, offering_id,address),
-        ExecuteMsg::WithdrawNft { offering_id,address } => execute_withdraw(deps, info, offering_id,address),
-        ExecuteMsg::ReceiveNft(msg) => execute_receive_nft(deps, info, msg),
+        ExecuteMsg::Buy { offering_id } => {
+            let funds = native_funds(deps.storage, info.funds)?;
+            execute_buy(deps, env, info.sender, funds, offering_id)
+        }
+        ExecuteMsg::BuyBatch { items, best_effort } => {
+            let funds = native_funds(deps.storage, info.funds)?;
+            execute_buy_batch(deps, env, info.sender, funds, items, best_effort)
+        }
+        ExecuteMsg::WithdrawNft { offering_id } => execute_withdraw(deps, info, offering_id),
+        ExecuteMsg::WithdrawBatch { offering_ids } => {
+            execute_withdraw_batch(deps, info, offering_ids)
+        }
+        ExecuteMsg::ListBatch { collection, items } => {
+            execute_list_batch(deps, env, info, collection, items)
+        }
+        ExecuteMsg::ListWithApproval { collection, item } => {
+            execute_list_with_approval(deps, env, info, collection, item)
+        }
+        ExecuteMsg::PruneListings { offering_ids } => {
+            execute_prune_listings(deps, env, offering_ids)
+        }
+        ExecuteMsg::PruneExpired { collection, start_after, limit } => {
+            execute_prune_expired(deps, env, collection, start_after, limit)
+        }
+        ExecuteMsg::UpdatePrice { offering_id, list_price } => {
+            execute_update_price(deps, info, offering_id, list_price)
+        }
+        ExecuteMsg::ReceiveNft(msg) => execute_receive_nft(deps, env, info, msg),
+        ExecuteMsg::Receive(msg) => execute_receive_cw20(deps, env, info, msg),
+        ExecuteMsg::SetFactory { factory } => execute_set_factory(deps, info, factory),
+        ExecuteMsg::AddCollection { address } => execute_add_collection(deps, info, address),
+        ExecuteMsg::DenyCollection { address } => execute_deny_collection(deps, info, address),
+        ExecuteMsg::SetRoyaltySupport { address, supported } => {
+            execute_set_royalty_support(deps, info, address, supported)
+        }
+        ExecuteMsg::AddDenom { denom, kind } => execute_add_denom(deps, info, denom, kind),
+        ExecuteMsg::RemoveDenom { denom } => execute_remove_denom(deps, info, denom),
         ExecuteMsg::WithdrawFees { amount, denom } => {
             execute_withdraw_fees(deps, info, amount, denom)
         }
         ExecuteMsg::ChangeFee { fee } => execute_change_fee(deps, info, fee),
+        ExecuteMsg::ProposeFee { fee } => execute_propose_fee(deps, env, info, fee),
+        ExecuteMsg::ApplyFee {} => execute_apply_fee(deps, env, info),
+        ExecuteMsg::SetCollectionFee { collection, fee } => {
+            execute_set_collection_fee(deps, info, collection, fee)
+        }
+        ExecuteMsg::SetFeeTiers { tiers } => execute_set_fee_tiers(deps, info, tiers),
+        ExecuteMsg::SetFeeLimits { max_fee, fee_timelock } => {
+            execute_set_fee_limits(deps, info, max_fee, fee_timelock)
+        }
+        ExecuteMsg::SetFeeRecipients { recipients } => {
+            execute_set_fee_recipients(deps, info, recipients)
+        }
+        ExecuteMsg::TransferOwnership { new_owner } => {
+            execute_transfer_ownership(deps, info, new_owner)
+        }
+        ExecuteMsg::AcceptOwnership {} => execute_accept_ownership(deps, info),
+        ExecuteMsg::SetRoles { fee_manager, pauser } => {
+            execute_set_roles(deps, info, fee_manager, pauser)
+        }
+        ExecuteMsg::Pause {} => execute_set_paused(deps, info, true),
+        ExecuteMsg::Unpause {} => execute_set_paused(deps, info, false),
+        ExecuteMsg::DistributeFees { denom } => execute_distribute_fees(deps, denom),
+        ExecuteMsg::PlaceBid { auction_id, address } => {
+            let funds = native_funds(deps.storage, info.funds)?;
+            execute_place_bid(deps, env, info.sender, funds, auction_id, address)
+        }
+        ExecuteMsg::SettleAuction { auction_id, address } => {
+            execute_settle_auction(deps, env, auction_id, address)
+        }
+        ExecuteMsg::MakeOffer { collection, token_id, expires } => {
+            let funds = native_funds(deps.storage, info.funds)?;
+            execute_make_offer(deps, env, info.sender, funds, collection, Some(token_id), expires)
+        }
+        ExecuteMsg::MakeCollectionOffer { collection, expires } => {
+            let funds = native_funds(deps.storage, info.funds)?;
+            execute_make_offer(deps, env, info.sender, funds, collection, None, expires)
+        }
+        ExecuteMsg::CancelOffer { offer_id } => execute_cancel_offer(deps, info, offer_id),
+        ExecuteMsg::ProposeTrade { counterparty, offered_nfts, requested_nfts, expires } => {
+            let funds = native_funds(deps.storage, info.funds)?;
+            execute_propose_trade(
+                deps,
+                env,
+                info.sender,
+                funds,
+                counterparty,
+                offered_nfts,
+                requested_nfts,
+                expires,
+            )
+        }
+        ExecuteMsg::CancelTrade { trade_id } => execute_cancel_trade(deps, env, info, trade_id),
+        ExecuteMsg::MigrateBatch { limit } => execute_migrate_batch(deps, limit),
     }
 }
 
+/// Messages that list, buy or escrow new funds, refused while paused.
+/// Withdrawing, cancelling and pruning keep working so users can exit.
+fn is_trading(msg: &ExecuteMsg) -> bool {
+    matches!(
+        msg,
+        ExecuteMsg::Buy { .. }
+            | ExecuteMsg::BuyBatch { .. }
+            | ExecuteMsg::ListBatch { .. }
+            | ExecuteMsg::ListWithApproval { .. }
+            | ExecuteMsg::ReceiveNft(_)
+            | ExecuteMsg::Receive(_)
+            | ExecuteMsg::PlaceBid { .. }
+            | ExecuteMsg::MakeOffer { .. }
+            | ExecuteMsg::MakeCollectionOffer { .. }
+            | ExecuteMsg::ProposeTrade { .. }
+    )
+}
+
 pub fn execute_buy(
     deps: DepsMut,
     env:Env,
-    info: MessageInfo,
-    offering_id: String,
-    address:String
+    buyer: Addr,
+    funds: Vec<Coin>,
+    offering_id: u64,
 ) -> Result<Response, ContractError> {
     // check if offering exists
-    let collection_info =  is_registered_collection(deps.as_ref(), &address).unwrap();
-    let off = OFFERINGS.load(deps.storage, (&address,&offering_id))?;
+    let off = offerings().load(deps.storage, offering_id)?;
 
-    if off.seller.eq(&info.sender) {
+    if off.seller.eq(&buyer) {
         return Err(ContractError::InvalidBuyer {});
     }
 
     // check for enough coins
-    let off_fund = get_fund(info.funds.clone(), off.list_price.denom.clone())?;
-    if off_fund.amount != off.list_price.amount.clone() {
+    let off_fund = get_fund(funds, off.list_price.denom.clone())?;
+    if off_fund.amount != off.list_price.amount {
         return Err(ContractError::InsufficientFunds {});
     }
 
-    let state = STATE.load(deps.storage)?;
-    let net_amount = Decimal::one().sub(state.fee).mul(off_fund.amount);
-    // create transfer msg
-    let transfer_msg: CosmosMsg = BankMsg::Send {
-        to_address: off.seller.clone().into(),
-        amount: vec![coin(net_amount.u128(), off_fund.denom.clone())],
-    }
-    .into();
-
-    //tvl update for all collection
-    STATE.update(deps.storage, |mut state| -> StdResult<_>{
-        state.tvl = state.tvl + off_fund.amount;
-        Ok(state)
-    })?;
-
-    //update sale history of this collection
-    SALEHISTORY.save(deps.storage, (&address,&(collection_info.sale_id+1).to_string()),&SaleHistoryInfo { 
-        from:off.seller.clone().to_string(),
-        to: info.sender.to_string(), 
-        denom: off.list_price.denom.clone(),
-        amount: off_fund.amount, 
-        time: env.block.time.seconds(),
-        nft_address:address.clone(),
-        token_id:off.token_id.clone()
-    })?;
-
-    //udpate tvl and sale id for this collection
-    COLLECTIONINFO.update(deps.storage, &address, 
-    |collection_info|->StdResult<_>{
-        let mut collection_info = collection_info.unwrap();
-        collection_info.sale_id += 1;
-        collection_info.tvl = collection_info.tvl + off.list_price.amount;
-        Ok(collection_info)
-    })?;
-
-    // create transfer cw721 msg
-    let cw721_transfer = Cw721ExecuteMsg::TransferNft {
-        recipient: info.sender.clone().into(),
-        token_id: off.token_id.clone(),
-    };
-    let cw721_transfer_msg: CosmosMsg = WasmMsg::Execute {
-        contract_addr: off.contract.clone().into(),
-        msg: to_binary(&cw721_transfer)?,
-        funds: vec![],
-    }
-    .into();
-
-    if collection_info.num_offerings == 1{    
-          OFFERINGS.remove( deps.storage, (&address,&offering_id));
-          COLLECTIONINFO.update(deps.storage, &address,
-             |collection_info|->StdResult<_>{
-                    let mut collection_info = collection_info.unwrap();
-                    collection_info.num_offerings = 0;
-                    Ok(collection_info)
-             })?;
-    }
-
-    else{
-        let crr_offering_id = collection_info.num_offerings;
-        let offering = OFFERINGS.may_load(deps.storage, (&address,&crr_offering_id.to_string()))?;
-        if offering!=None{
-            OFFERINGS.save(deps.storage, (&address,&offering_id.to_string()), &offering.unwrap())?;
-           
-            COLLECTIONINFO.update(deps.storage, &address,
-                |collection_info|->StdResult<_>{
-                        let mut collection_info = collection_info.unwrap();
-                        collection_info.num_offerings =collection_info.num_offerings-1;
-                        Ok(collection_info)
-                })?;
-        OFFERINGS.remove( deps.storage, (&address,&crr_offering_id.to_string()));
-  
-         }
-    }
+    let (messages, royalty_amount) = close_sale(deps, &env, &buyer, offering_id, &off)?;
 
     let price_string = format!("{}{}", off_fund.amount, off_fund.denom);
     let res = Response::new()
         .add_attribute("action", "buy_nft")
-        .add_attribute("buyer", info.sender)
+        .add_attribute("buyer", buyer)
         .add_attribute("seller", off.seller)
         .add_attribute("paid_price", price_string)
+        .add_attribute("royalty_amount", royalty_amount)
         .add_attribute("token_id", off.token_id)
         .add_attribute("nft_contract", off.contract)
-        .add_messages(vec![transfer_msg, cw721_transfer_msg]);
+        .add_messages(messages);
     Ok(res)
 }
 
-pub fn execute_withdraw(
+/// Buys several listings with one payment and refunds what is left of it. In
+/// best-effort mode, listings that can not be bought are skipped instead of
+/// failing the whole batch.
+pub fn execute_buy_batch(
     deps: DepsMut,
-    info: MessageInfo,
-    offering_id: String,
-    address: String
+    env: Env,
+    buyer: Addr,
+    funds: Vec<Coin>,
+    items: Vec<(String, u64)>,
+    best_effort: bool,
 ) -> Result<Response, ContractError> {
-    let off = OFFERINGS.load(deps.storage, (&address,&offering_id)).unwrap();
-    if off.seller.ne(&info.sender) {
-        return Err(ContractError::Unauthorized {});
+    let mut remaining = funds;
+    let mut messages = vec![];
+    let mut bought = vec![];
+    let mut skipped = vec![];
+    for (collection, offering_id) in items {
+        let checked =
+            check_batch_item(deps.as_ref(), &env, &buyer, &remaining, &collection, offering_id);
+        let (off, item_messages, royalty_amount, fee_amount) = match checked {
+            Ok(sale) => sale,
+            Err(_) if best_effort => {
+                skipped.push(offering_id.to_string());
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        // nothing of the item is written before this point, so a failure from
+        // here on fails the whole batch instead of keeping half a sale
+        finish_sale(deps.storage, &env, &buyer, offering_id, &off, royalty_amount, fee_amount)?;
+        if let Some(fund) = remaining.iter_mut().find(|fund| fund.denom == off.list_price.denom) {
+            fund.amount -= off.list_price.amount;
+        }
+        messages.extend(item_messages);
+        bought.push(offering_id.to_string());
     }
 
-    let collection_info = is_registered_collection(deps.as_ref(), &address)?;
+    for refund in remaining.into_iter().filter(|fund| !fund.amount.is_zero()) {
+        messages.push(payment_msg(deps.storage, buyer.as_str(), refund)?);
+    }
 
-    let transfer_cw721_msg = Cw721ExecuteMsg::TransferNft {
-        recipient: off.seller.into(),
-        token_id: off.token_id.clone(),
-    };
+    Ok(Response::new()
+        .add_attribute("action", "buy_batch")
+        .add_attribute("buyer", buyer)
+        .add_attribute("bought", bought.join(","))
+        .add_attribute("skipped", skipped.join(","))
+        .add_messages(messages))
+}
 
-    let exec_cw721_transfer: CosmosMsg = WasmMsg::Execute {
-        contract_addr: off.contract.into(),
-        msg: to_binary(&transfer_cw721_msg)?,
-        funds: vec![],
+/// Checks that one listing of a batch can be bought out of `remaining`, without
+/// writing anything. Returns the listing, its payout and NFT transfer messages
+/// and the royalty and fee amounts.
+fn check_batch_item(
+    deps: Deps,
+    env: &Env,
+    buyer: &Addr,
+    remaining: &[Coin],
+    collection: &str,
+    offering_id: u64,
+) -> Result<(Offering, Vec<CosmosMsg>, Uint128, Uint128), ContractError> {
+    let off = offerings().load(deps.storage, offering_id)?;
+    if off.contract.as_str() != collection {
+        return Err(ContractError::OfferMismatch {});
     }
-    .into();
-
-    if collection_info.num_offerings == 1{    
-          OFFERINGS.remove( deps.storage, (&address,&offering_id));
-          COLLECTIONINFO.update(deps.storage, &address,
-             |collection_info|->StdResult<_>{
-                    let mut collection_info = collection_info.unwrap();
-                    collection_info.num_offerings = 0;
-                    Ok(collection_info)
-             })?;
-     }
-
-     else{
-        let crr_offering_id = collection_info.num_offerings;
-        let offering = OFFERINGS.may_load(deps.storage, (&address,&crr_offering_id.to_string()))?;
-        if offering!=None{
-            OFFERINGS.save(deps.storage, (&address,&offering_id.to_string()), &offering.unwrap())?;
-           
-            COLLECTIONINFO.update(deps.storage, &address,
-                |collection_info|->StdResult<_>{
-                        let mut collection_info = collection_info.unwrap();
-                        collection_info.num_offerings =collection_info.num_offerings-1;
-                        Ok(collection_info)
-                })?;
-        OFFERINGS.remove( deps.storage, (&address,&crr_offering_id.to_string()));
-  
-         }
+    if off.seller.eq(buyer) {
+        return Err(ContractError::InvalidBuyer {});
+    }
+
+    let fund = remaining
+        .iter()
+        .find(|fund| fund.denom == off.list_price.denom)
+        .ok_or(ContractError::InsufficientFunds {})?;
+    if fund.amount < off.list_price.amount {
+        return Err(ContractError::InsufficientFunds {});
+    }
+
+    let (messages, royalty_amount, fee_amount) = check_sale(deps, env, buyer, &off)?;
+    Ok((off, messages, royalty_amount, fee_amount))
+}
+
+/// Pays out a listing bought at its list price, records the sale and removes the
+/// listing. Returns the payout and NFT transfer messages and the royalty amount.
+/// The transfer works the same for escrowed and approved NFTs.
+fn close_sale(
+    deps: DepsMut,
+    env: &Env,
+    buyer: &Addr,
+    offering_id: u64,
+    off: &Offering,
+) -> Result<(Vec<CosmosMsg>, Uint128), ContractError> {
+    let (messages, royalty_amount, fee_amount) = check_sale(deps.as_ref(), env, buyer, off)?;
+    finish_sale(deps.storage, env, buyer, offering_id, off, royalty_amount, fee_amount)?;
+    Ok((messages, royalty_amount))
+}
+
+/// The read-only half of `close_sale`: checks the listing can be bought by
+/// `buyer` and returns the messages and the royalty and fee amounts.
+fn check_sale(
+    deps: Deps,
+    env: &Env,
+    buyer: &Addr,
+    off: &Offering,
+) -> Result<(Vec<CosmosMsg>, Uint128, Uint128), ContractError> {
+    if off.expires.is_expired(&env.block) {
+        return Err(ContractError::ListingExpired {});
     }
+    if matches!(&off.reserved_for, Some(reserved) if reserved != buyer) {
+        return Err(ContractError::InvalidBuyer {});
+    }
+    if off.custody == Custody::Approval && !is_approval_valid(deps, env, off) {
+        return Err(ContractError::StaleListing {});
+    }
+
+    let (mut messages, royalty_amount, fee_amount) =
+        sale_payouts(deps, &off.contract, &off.token_id, &off.seller, &off.list_price)?;
+    messages.push(transfer_nft_msg(&off.contract, buyer.as_str(), &off.token_id)?);
+    Ok((messages, royalty_amount, fee_amount))
+}
+
+/// The writing half of `close_sale`: records the sale and removes the listing.
+fn finish_sale(
+    storage: &mut dyn Storage,
+    env: &Env,
+    buyer: &Addr,
+    offering_id: u64,
+    off: &Offering,
+    royalty_amount: Uint128,
+    fee_amount: Uint128,
+) -> StdResult<()> {
+    record_sale(storage, SaleHistoryInfo {
+        from: off.seller.to_string(),
+        to: buyer.to_string(),
+        denom: off.list_price.denom.clone(),
+        amount: off.list_price.amount,
+        royalty_amount,
+        fee_amount,
+        time: env.block.time.seconds(),
+        nft_address: off.contract.to_string(),
+        token_id: off.token_id.clone(),
+    })?;
+
+    offerings().remove(storage, offering_id)?;
+    decrement_offerings(storage, off.contract.as_str())
+}
+
+pub fn execute_withdraw(
+    deps: DepsMut,
+    info: MessageInfo,
+    offering_id: u64,
+) -> Result<Response, ContractError> {
+    let exec_cw721_transfer = withdraw_offering(deps.storage, &info.sender, offering_id)?;
 
     let res = Response::new()
         .add_attribute("action", "withdraw_nft")
         .add_attribute("seller", info.sender)
-        .add_message(exec_cw721_transfer);
+        .add_attribute("offering_id", offering_id.to_string())
+        .add_messages(exec_cw721_transfer);
     Ok(res)
 }
 
+pub fn execute_withdraw_batch(
+    deps: DepsMut,
+    info: MessageInfo,
+    offering_ids: Vec<u64>,
+) -> Result<Response, ContractError> {
+    let mut messages = vec![];
+    for offering_id in &offering_ids {
+        messages.extend(withdraw_offering(deps.storage, &info.sender, *offering_id)?);
+    }
+
+    let ids: Vec<String> = offering_ids.iter().map(u64::to_string).collect();
+    Ok(Response::new()
+        .add_attribute("action", "withdraw_batch")
+        .add_attribute("seller", info.sender)
+        .add_attribute("offering_ids", ids.join(","))
+        .add_messages(messages))
+}
+
+/// Removes a listing of `seller` and returns the message sending an escrowed NFT back.
+fn withdraw_offering(
+    storage: &mut dyn Storage,
+    seller: &Addr,
+    offering_id: u64,
+) -> Result<Option<CosmosMsg>, ContractError> {
+    let off = offerings().load(storage, offering_id)?;
+    if off.seller.ne(seller) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    offerings().remove(storage, offering_id)?;
+    decrement_offerings(storage, off.contract.as_str())?;
+    match off.custody {
+        Custody::Escrow => {
+            let transfer = transfer_nft_msg(&off.contract, off.seller.as_str(), &off.token_id)?;
+            Ok(Some(transfer))
+        }
+        Custody::Approval => Ok(None),
+    }
+}
+
+pub fn execute_update_price(
+    deps: DepsMut,
+    info: MessageInfo,
+    offering_id: u64,
+    list_price: Coin,
+) -> Result<Response, ContractError> {
+    let mut off = offerings().load(deps.storage, offering_id)?;
+    if off.seller.ne(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    check_denom(deps.storage, &list_price.denom)?;
+
+    let old_price = format!("{}{}", off.list_price.amount, off.list_price.denom);
+    off.list_price = list_price;
+    offerings().save(deps.storage, offering_id, &off)?;
+
+    let price_string = format!("{}{}", off.list_price.amount, off.list_price.denom);
+    let event = Event::new("price_updated")
+        .add_attribute("offering_id", offering_id.to_string())
+        .add_attribute("nft_contract", off.contract)
+        .add_attribute("token_id", off.token_id)
+        .add_attribute("seller", off.seller)
+        .add_attribute("old_price", old_price)
+        .add_attribute("list_price", price_string);
+    Ok(Response::new()
+        .add_attribute("action", "update_price")
+        .add_event(event))
+}
+
 pub fn execute_receive_nft(
     deps: DepsMut,
+    env: Env,
     info: MessageInfo,
     wrapper: Cw721ReceiveMsg,
 ) -> Result<Response, ContractError> {
-    let msg: SellNft = from_binary(&wrapper.msg)?;
-    let nft_address = info.sender.to_string();
-    let state = STATE.load(deps.storage)?;
+    // a bare SellNft is a fixed-price listing, as it was before auctions existed
+    if let Ok(msg) = from_binary::<SellNft>(&wrapper.msg) {
+        // info.sender is the cw721 contract, which anybody can deploy
+        is_registered_collection(deps.as_ref(), &info.sender)?;
+        return execute_sell_nft(deps, env, info, wrapper, msg);
+    }
+
+    let msg: ReceiveNftMsg = from_binary(&wrapper.msg)?;
+    // a trade names its collections, so any cw721 can take part
+    if !matches!(msg, ReceiveNftMsg::AcceptTrade { .. }) {
+        is_registered_collection(deps.as_ref(), &info.sender)?;
+    }
+    match msg {
+        ReceiveNftMsg::StartAuction(msg) => {
+            execute_start_auction(deps, env, info, wrapper.sender, wrapper.token_id, msg)
+        }
+        ReceiveNftMsg::AcceptOffer { offer_id } => {
+            execute_accept_offer(deps, env, info, wrapper.sender, wrapper.token_id, offer_id)
+        }
+        ReceiveNftMsg::AcceptTrade { trade_id } => {
+            execute_accept_trade(deps, env, info.sender, wrapper.sender, wrapper.token_id, trade_id)
+        }
+    }
+}
+
+pub fn execute_receive_cw20(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    wrapper: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    // info.sender is the token contract. A removed token can still pay for
+    // listings made while it was accepted.
+    let token = info.sender.to_string();
+    match DENOMS.may_load(deps.storage, &token)? {
+        Some(DenomInfo { kind: DenomKind::Cw20, .. }) => {}
+        _ => return Err(ContractError::WrongDenom {}),
+    }
+    let sender = deps.api.addr_validate(&wrapper.sender)?;
+    let funds = vec![coin(wrapper.amount.u128(), token)];
 
-    if msg.list_price.denom != state.denom{
-        return Err(ContractError::WrongDenom {  });
+    match from_binary(&wrapper.msg)? {
+        Cw20HookMsg::Buy { offering_id } => execute_buy(deps, env, sender, funds, offering_id),
+        Cw20HookMsg::BuyBatch { items, best_effort } => {
+            execute_buy_batch(deps, env, sender, funds, items, best_effort)
+        }
+        Cw20HookMsg::PlaceBid { auction_id, address } => {
+            execute_place_bid(deps, env, sender, funds, auction_id, address)
+        }
+        Cw20HookMsg::MakeOffer { collection, token_id, expires } => {
+            execute_make_offer(deps, env, sender, funds, collection, Some(token_id), expires)
+        }
+        Cw20HookMsg::MakeCollectionOffer { collection, expires } => {
+            execute_make_offer(deps, env, sender, funds, collection, None, expires)
+        }
     }
-    
-    let id = increment_offerings(deps.storage,nft_address.clone())?.to_string();
+}
 
-    
-    // save Offering
+pub fn execute_sell_nft(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    wrapper: Cw721ReceiveMsg,
+    msg: SellNft,
+) -> Result<Response, ContractError> {
     let off = Offering {
         contract: info.sender.clone(),
         token_id: wrapper.token_id,
         seller: deps.api.addr_validate(&wrapper.sender)?,
         list_price: msg.list_price.clone(),
-        image_url:msg.image_url
+        image_url:msg.image_url,
+        custody: Custody::Escrow,
+        expires: msg.expires.unwrap_or_default(),
+        reserved_for: maybe_addr(deps.api, msg.reserved_for)?,
     };
-    OFFERINGS.save(deps.storage, (&nft_address,&id), &off)?;
+    let id = save_offering(deps.storage, &env, &off)?;
 
     let price_string = format!("{}{}", msg.list_price.amount, msg.list_price.denom);
     let res = Response::new()
         .add_attribute("action", "sell_nft")
-        .add_attribute("offering_id", id)
+        .add_attribute("offering_id", id.to_string())
         .add_attribute("nft_contract", info.sender.to_string())
         .add_attribute("seller", off.seller)
         .add_attribute("list_price", price_string)
@@ -260,144 +611,1512 @@ pub fn execute_receive_nft(
     Ok(res)
 }
 
-pub fn execute_withdraw_fees(
+/// Lists tokens the sender approved the marketplace for with `ApproveAll`. The
+/// marketplace pulls them into escrow with `TransferNft`.
+pub fn execute_list_batch(
     deps: DepsMut,
+    env: Env,
     info: MessageInfo,
-    amount: Uint128,
-    denom: String,
+    collection: String,
+    items: Vec<ListItem>,
 ) -> Result<Response, ContractError> {
-    let state = STATE.load(deps.storage)?;
-
-    if state.owner.ne(&info.sender) {
-        return Err(ContractError::Unauthorized {});
-    }
+    let collection = deps.api.addr_validate(&collection)?;
+    is_registered_collection(deps.as_ref(), &collection)?;
+
+    let mut messages = vec![];
+    let mut ids = vec![];
+    for item in items {
+        // as an operator the marketplace could pull anyone's token, so check the owner
+        let owner = query_nft_owner(deps.as_ref(), &collection, &item.token_id)?;
+        if owner.owner != info.sender {
+            return Err(ContractError::Unauthorized {});
+        }
 
-    let transfer: CosmosMsg = BankMsg::Send {
-        to_address: state.owner.into(),
-        amount: vec![coin(amount.into(), denom)],
+        let off = Offering {
+            contract: collection.clone(),
+            token_id: item.token_id,
+            seller: info.sender.clone(),
+            list_price: item.list_price,
+            image_url: item.image_url,
+            custody: Custody::Escrow,
+            expires: item.expires.unwrap_or_default(),
+            reserved_for: maybe_addr(deps.api, item.reserved_for)?,
+        };
+        ids.push(save_offering(deps.storage, &env, &off)?.to_string());
+        messages.push(transfer_nft_msg(&collection, env.contract.address.as_str(), &off.token_id)?);
     }
-    .into();
 
-    Ok(Response::new().add_message(transfer))
+    Ok(Response::new()
+        .add_attribute("action", "list_batch")
+        .add_attribute("nft_contract", collection)
+        .add_attribute("seller", info.sender)
+        .add_attribute("offering_ids", ids.join(","))
+        .add_messages(messages))
 }
 
-pub fn execute_change_fee(
+/// Lists a token that stays in the seller's wallet. The seller must own it and
+/// have approved the marketplace for it with `Approve`.
+pub fn execute_list_with_approval(
     deps: DepsMut,
+    env: Env,
     info: MessageInfo,
-    fee: Decimal,
+    collection: String,
+    item: ListItem,
 ) -> Result<Response, ContractError> {
-    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-        if state.owner.ne(&info.sender) {
-            return Err(ContractError::Unauthorized {});
-        }
+    let collection = deps.api.addr_validate(&collection)?;
+    is_registered_collection(deps.as_ref(), &collection)?;
 
-        state.fee = fee;
-        Ok(state)
-    })?;
+    let off = Offering {
+        contract: collection,
+        token_id: item.token_id,
+        seller: info.sender,
+        list_price: item.list_price,
+        image_url: item.image_url,
+        custody: Custody::Approval,
+        expires: item.expires.unwrap_or_default(),
+        reserved_for: maybe_addr(deps.api, item.reserved_for)?,
+    };
+    if !is_approval_valid(deps.as_ref(), &env, &off) {
+        return Err(ContractError::NotApproved {});
+    }
+    let id = save_offering(deps.storage, &env, &off)?;
 
-    let res = Response::new()
-        .add_attribute("action", "change_fee")
-        .add_attribute("fee", fee.to_string());
-    Ok(res)
+    let price_string = format!("{}{}", off.list_price.amount, off.list_price.denom);
+    Ok(Response::new()
+        .add_attribute("action", "list_with_approval")
+        .add_attribute("offering_id", id.to_string())
+        .add_attribute("nft_contract", off.contract)
+        .add_attribute("seller", off.seller)
+        .add_attribute("list_price", price_string)
+        .add_attribute("token_id", off.token_id))
 }
 
-pub fn execute_add_collection(
+/// anyone. Removes approval listings whose token was moved or whose approval was
+/// revoked or expired. Other ids are ignored.
+pub fn execute_prune_listings(
     deps: DepsMut,
-    info: MessageInfo,
-    address: String,
+    env: Env,
+    offering_ids: Vec<u64>,
 ) -> Result<Response, ContractError> {
-
-    let state = STATE.load(deps.storage)?;
-    
-    if state.owner.ne(&info.sender) {
-            return Err(ContractError::Unauthorized {});
+    let mut pruned = vec![];
+    for offering_id in offering_ids {
+        let off = match offerings().may_load(deps.storage, offering_id)? {
+            Some(off) => off,
+            None => continue,
+        };
+        if off.custody == Custody::Approval && !is_approval_valid(deps.as_ref(), &env, &off) {
+            offerings().remove(deps.storage, offering_id)?;
+            decrement_offerings(deps.storage, off.contract.as_str())?;
+            pruned.push(offering_id.to_string());
         }
+    }
 
-   
-    let res = Response::new()
-        .add_attribute("action", "register collection")
-        .add_attribute("address", address);
-    Ok(res)
+    Ok(Response::new()
+        .add_attribute("action", "prune_listings")
+        .add_attribute("offering_ids", pruned.join(",")))
 }
 
-
-pub fn is_registered_collection(
-    deps:Deps,
-    address:&String
-)->Result<CollectionInfo,ContractError>{
-    let collection_info = COLLECTIONINFO.may_load(deps.storage, address)?;
-    if collection_info == None{
-        return Err(ContractError::NotListedNFt{});
-    }
-    else{
-        Ok(collection_info.unwrap())
+pub fn execute_prune_expired(
+    deps: DepsMut,
+    env: Env,
+    collection: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let collection = deps.api.addr_validate(&collection)?;
+
+    // the scan is bounded too, live listings cost as much to read as expired ones
+    let mut last_offering_id = None;
+    let mut expired = vec![];
+    let items = offerings()
+        .idx
+        .collection
+        .prefix(collection)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(PRUNE_SCAN_LIMIT);
+    for item in items {
+        let (offering_id, off) = item?;
+        last_offering_id = Some(offering_id);
+        if off.expires.is_expired(&env.block) {
+            expired.push((offering_id, off));
+            if expired.len() == limit {
+                break;
+            }
+        }
     }
-}
 
-#[entry_point]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::GetStateInfo{} => to_binary(&query_state(deps)?),
-        QueryMsg::GetCollectionInfo { address } => to_binary(&query_collection_info(deps,address)?),
-        QueryMsg::GetFee {} => to_binary(&query_fee(deps)?),
-        QueryMsg::GetOffers { page_num, page_count,address }  => {
-            to_binary(&query_all(deps,  page_num, page_count,address )?)
-        },
-        QueryMsg::GetSaleHistory { page_num, count,address }  => {
-            to_binary(&query_sale_history(deps,  page_num, count,address )?)
+    let mut messages = vec![];
+    let mut pruned = vec![];
+    for (offering_id, off) in expired {
+        offerings().remove(deps.storage, offering_id)?;
+        decrement_offerings(deps.storage, off.contract.as_str())?;
+        if off.custody == Custody::Escrow {
+            messages.push(transfer_nft_msg(&off.contract, off.seller.as_str(), &off.token_id)?);
         }
+        pruned.push(offering_id.to_string());
     }
-}
 
-fn query_state(deps: Deps) -> StdResult<State> {
-    let state = STATE.load(deps.storage)?;
-    Ok(state)
+    Ok(Response::new()
+        .add_attribute("action", "prune_expired")
+        .add_attribute("offering_ids", pruned.join(","))
+        .add_attribute(
+            "last_offering_id",
+            last_offering_id.map(|id| id.to_string()).unwrap_or_default(),
+        )
+        .add_messages(messages))
 }
 
-fn query_collection_info(deps: Deps, address: String) -> StdResult<CollectionInfo>{
-    let collection_info = is_registered_collection(deps, &address).unwrap();
-    Ok(collection_info)
+/// Whether the seller still owns the token and the marketplace may transfer it.
+/// A token that can not be queried any more (e.g. burned) is not valid.
+fn is_approval_valid(deps: Deps, env: &Env, off: &Offering) -> bool {
+    let owner: OwnerOfResponse = match deps.querier.query_wasm_smart(
+        &off.contract,
+        &Cw721QueryMsg::OwnerOf {
+            token_id: off.token_id.clone(),
+            include_expired: Some(false),
+        },
+    ) {
+        Ok(owner) => owner,
+        Err(_) => return false,
+    };
+    owner.owner == off.seller.as_str()
+        && owner.approvals.iter().any(|approval| {
+            approval.spender == env.contract.address.as_str()
+                && !approval.expires.is_expired(&env.block)
+        })
 }
 
-fn query_fee(deps: Deps) -> StdResult<FeeResponse> {
-    let state = STATE.load(deps.storage)?;
-    Ok(FeeResponse { fee: state.fee })
+/// Saves a new listing under a fresh id and returns the id.
+fn save_offering(storage: &mut dyn Storage, env: &Env, off: &Offering) -> Result<u64, ContractError> {
+    check_denom(storage, &off.list_price.denom)?;
+    if off.expires.is_expired(&env.block) {
+        return Err(ContractError::InvalidExpiration {});
+    }
+
+    let id = next_offering_id(storage)?;
+    increment_offerings(storage, off.contract.as_str())?;
+    offerings().save(storage, id, off)?;
+    Ok(id)
 }
 
-fn query_all(
-    deps: Deps,
-    page_num: u32,
-    page_count: u32,
-    address:String
-) -> StdResult<Vec<OfferingResult>> {
-    if page_num == 0 {
-        let offerings:Vec<OfferingResult> = Vec::new(); 
-        Ok(offerings)
-    }
-    else{
-        let mut offerings:Vec<OfferingResult> = Vec::new(); 
-        for i in (page_num-1)*page_count+1 .. page_num*page_count+1{
-            let offering = OFFERINGS.may_load(deps.storage, (&address,&i.to_string()))?;
-            if offering == None{
-                continue;
-            }
-            else{
-                let offering = offering.unwrap();
-                offerings.push(OfferingResult{
-                    id: i.to_string(),
-                    token_id:offering.token_id,
-                    contract:offering.contract,
-                    seller:offering.seller,
-                    list_price:offering.list_price,
-                    image_url:offering.image_url
-                }); 
-            }
-        }
-        Ok(offerings)
+pub fn execute_start_auction(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    seller: String,
+    token_id: String,
+    msg: StartAuction,
+) -> Result<Response, ContractError> {
+    check_denom(deps.storage, &msg.reserve_price.denom)?;
+
+    if msg.min_bid_increment.is_zero()
+        || msg.start_time >= msg.end_time
+        || msg.end_time <= env.block.time.seconds()
+    {
+        return Err(ContractError::InvalidAuction {});
     }
-    
-}
 
+    let id = next_auction_id(deps.storage)?;
+    let auction = Auction {
+        token_id,
+        contract: info.sender.clone(),
+        seller: deps.api.addr_validate(&seller)?,
+        reserve_price: msg.reserve_price,
+        min_bid_increment: msg.min_bid_increment,
+        start_time: msg.start_time,
+        end_time: msg.end_time,
+        image_url: msg.image_url,
+        highest_bid: None,
+    };
+    AUCTIONS.save(deps.storage, (info.sender.as_str(), id), &auction)?;
+
+    let price_string = format!("{}{}", auction.reserve_price.amount, auction.reserve_price.denom);
+    Ok(Response::new()
+        .add_attribute("action", "start_auction")
+        .add_attribute("auction_id", id.to_string())
+        .add_attribute("nft_contract", info.sender)
+        .add_attribute("seller", auction.seller)
+        .add_attribute("reserve_price", price_string)
+        .add_attribute("end_time", auction.end_time.to_string())
+        .add_attribute("token_id", auction.token_id))
+}
+
+pub fn execute_place_bid(
+    deps: DepsMut,
+    env: Env,
+    bidder: Addr,
+    funds: Vec<Coin>,
+    auction_id: u64,
+    address: String,
+) -> Result<Response, ContractError> {
+    let mut auction = AUCTIONS.load(deps.storage, (&address, auction_id))?;
+
+    let now = env.block.time.seconds();
+    if now < auction.start_time {
+        return Err(ContractError::AuctionNotStarted {});
+    }
+    if now >= auction.end_time {
+        return Err(ContractError::AuctionEnded {});
+    }
+    if auction.seller.eq(&bidder) {
+        return Err(ContractError::InvalidBuyer {});
+    }
+
+    let denom = auction.reserve_price.denom.clone();
+    let bid_fund = get_fund(funds, denom.clone())?;
+    let min_bid = match &auction.highest_bid {
+        Some(bid) => bid.amount + auction.min_bid_increment,
+        None => auction.reserve_price.amount,
+    };
+    if bid_fund.amount < min_bid {
+        return Err(ContractError::BidTooLow { min: min_bid });
+    }
+
+    // refund the bidder we just outbid
+    let mut messages: Vec<CosmosMsg> = vec![];
+    if let Some(outbid) = auction.highest_bid.take() {
+        messages.push(payment_msg(
+            deps.storage,
+            outbid.bidder.as_str(),
+            coin(outbid.amount.u128(), denom),
+        )?);
+    }
+
+    // anti-sniping: late bids give everyone else a chance to respond
+    if auction.end_time - now < AUCTION_EXTENSION_WINDOW {
+        auction.end_time = now + AUCTION_EXTENSION_WINDOW;
+    }
+
+    auction.highest_bid = Some(Bid {
+        bidder: bidder.clone(),
+        amount: bid_fund.amount,
+        time: now,
+    });
+    AUCTIONS.save(deps.storage, (&address, auction_id), &auction)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "place_bid")
+        .add_attribute("auction_id", auction_id.to_string())
+        .add_attribute("bidder", bidder)
+        .add_attribute("amount", bid_fund.amount)
+        .add_attribute("end_time", auction.end_time.to_string())
+        .add_messages(messages))
+}
+
+pub fn execute_settle_auction(
+    deps: DepsMut,
+    env: Env,
+    auction_id: u64,
+    address: String,
+) -> Result<Response, ContractError> {
+    let auction = AUCTIONS.load(deps.storage, (&address, auction_id))?;
+    if env.block.time.seconds() < auction.end_time {
+        return Err(ContractError::AuctionNotEnded {});
+    }
+    AUCTIONS.remove(deps.storage, (&address, auction_id));
+
+    let res = Response::new()
+        .add_attribute("action", "settle_auction")
+        .add_attribute("auction_id", auction_id.to_string())
+        .add_attribute("seller", auction.seller.clone())
+        .add_attribute("token_id", auction.token_id.clone())
+        .add_attribute("nft_contract", auction.contract.clone());
+
+    let bid = match auction.highest_bid {
+        Some(bid) => bid,
+        // nobody met the reserve price, the seller gets the NFT back
+        None => {
+            let return_msg =
+                transfer_nft_msg(&auction.contract, auction.seller.as_str(), &auction.token_id)?;
+            return Ok(res.add_message(return_msg));
+        }
+    };
+
+    let price = coin(bid.amount.u128(), auction.reserve_price.denom);
+    let (mut messages, royalty_amount, fee_amount) =
+        sale_payouts(deps.as_ref(), &auction.contract, &auction.token_id, &auction.seller, &price)?;
+
+    record_sale(deps.storage, SaleHistoryInfo {
+        from: auction.seller.to_string(),
+        to: bid.bidder.to_string(),
+        denom: price.denom.clone(),
+        amount: price.amount,
+        royalty_amount,
+        fee_amount,
+        time: env.block.time.seconds(),
+        nft_address: address,
+        token_id: auction.token_id.clone(),
+    })?;
+
+    messages.push(transfer_nft_msg(&auction.contract, bid.bidder.as_str(), &auction.token_id)?);
+
+    let price_string = format!("{}{}", price.amount, price.denom);
+    Ok(res
+        .add_attribute("buyer", bid.bidder)
+        .add_attribute("paid_price", price_string)
+        .add_attribute("royalty_amount", royalty_amount)
+        .add_messages(messages))
+}
+
+pub fn execute_make_offer(
+    deps: DepsMut,
+    env: Env,
+    bidder: Addr,
+    funds: Vec<Coin>,
+    collection: String,
+    token_id: Option<String>,
+    expires: Expiration,
+) -> Result<Response, ContractError> {
+    // the offer is made in whichever accepted denom was sent
+    let price = match funds.as_slice() {
+        [price] => price.clone(),
+        _ => return Err(ContractError::InsufficientFunds {}),
+    };
+    check_denom(deps.storage, &price.denom)?;
+    if price.amount.is_zero() {
+        return Err(ContractError::InsufficientFunds {});
+    }
+    if expires.is_expired(&env.block) {
+        return Err(ContractError::InvalidExpiration {});
+    }
+    let collection = deps.api.addr_validate(&collection)?;
+    is_registered_collection(deps.as_ref(), &collection)?;
+
+    let action = match token_id {
+        Some(_) => "make_offer",
+        None => "make_collection_offer",
+    };
+    let offer = BuyOffer {
+        bidder,
+        collection,
+        token_id,
+        price,
+        expires,
+    };
+    let id = next_buy_offer_id(deps.storage)?;
+    buy_offers().save(deps.storage, id, &offer)?;
+
+    let price_string = format!("{}{}", offer.price.amount, offer.price.denom);
+    Ok(Response::new()
+        .add_attribute("action", action)
+        .add_attribute("offer_id", id.to_string())
+        .add_attribute("bidder", offer.bidder)
+        .add_attribute("nft_contract", offer.collection)
+        .add_attribute("token_id", offer.token_id.unwrap_or_default())
+        .add_attribute("price", price_string))
+}
+
+pub fn execute_cancel_offer(
+    deps: DepsMut,
+    info: MessageInfo,
+    offer_id: u64,
+) -> Result<Response, ContractError> {
+    let offer = buy_offers().load(deps.storage, offer_id)?;
+    if offer.bidder.ne(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    buy_offers().remove(deps.storage, offer_id)?;
+
+    let refund = payment_msg(deps.storage, offer.bidder.as_str(), offer.price)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_offer")
+        .add_attribute("offer_id", offer_id.to_string())
+        .add_message(refund))
+}
+
+pub fn execute_accept_offer(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    seller: String,
+    token_id: String,
+    offer_id: u64,
+) -> Result<Response, ContractError> {
+    let offer = buy_offers().load(deps.storage, offer_id)?;
+
+    // info.sender is the cw721 contract that sent us the NFT
+    if offer.collection.ne(&info.sender) {
+        return Err(ContractError::OfferMismatch {});
+    }
+    if matches!(&offer.token_id, Some(id) if *id != token_id) {
+        return Err(ContractError::OfferMismatch {});
+    }
+    if offer.expires.is_expired(&env.block) {
+        return Err(ContractError::OfferExpired {});
+    }
+
+    let seller = deps.api.addr_validate(&seller)?;
+    if seller.eq(&offer.bidder) {
+        return Err(ContractError::InvalidBuyer {});
+    }
+    buy_offers().remove(deps.storage, offer_id)?;
+
+    let (mut messages, royalty_amount, fee_amount) =
+        sale_payouts(deps.as_ref(), &offer.collection, &token_id, &seller, &offer.price)?;
+
+    record_sale(deps.storage, SaleHistoryInfo {
+        from: seller.to_string(),
+        to: offer.bidder.to_string(),
+        denom: offer.price.denom.clone(),
+        amount: offer.price.amount,
+        royalty_amount,
+        fee_amount,
+        time: env.block.time.seconds(),
+        nft_address: offer.collection.to_string(),
+        token_id: token_id.clone(),
+    })?;
+
+    messages.push(transfer_nft_msg(&offer.collection, offer.bidder.as_str(), &token_id)?);
+
+    let price_string = format!("{}{}", offer.price.amount, offer.price.denom);
+    Ok(Response::new()
+        .add_attribute("action", "accept_offer")
+        .add_attribute("offer_id", offer_id.to_string())
+        .add_attribute("buyer", offer.bidder)
+        .add_attribute("seller", seller)
+        .add_attribute("paid_price", price_string)
+        .add_attribute("royalty_amount", royalty_amount)
+        .add_attribute("token_id", token_id)
+        .add_attribute("nft_contract", offer.collection)
+        .add_messages(messages))
+}
+
+pub fn execute_withdraw_fees(
+    deps: DepsMut,
+    info: MessageInfo,
+    amount: Uint128,
+    denom: String,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+
+    if state.fee_manager.ne(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // everything else the contract holds is escrowed for bids and offers
+    ACCRUED_FEES.update(deps.storage, &denom, |fees| -> Result<_, ContractError> {
+        fees.unwrap_or_default()
+            .checked_sub(amount)
+            .map_err(|_| ContractError::InsufficientFunds {})
+    })?;
+
+    let transfer = payment_msg(deps.storage, state.fee_manager.as_str(), coin(amount.u128(), denom))?;
+
+    Ok(Response::new()
+        .add_attribute("action", "withdraw_fees")
+        .add_attribute("amount", amount)
+        .add_message(transfer))
+}
+
+pub fn execute_set_fee_recipients(
+    deps: DepsMut,
+    info: MessageInfo,
+    recipients: Vec<AdminInfo>,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    if state.fee_manager.ne(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut total = Decimal::zero();
+    for recipient in &recipients {
+        deps.api.addr_validate(&recipient.address)?;
+        total += recipient.portion;
+    }
+    if total != Decimal::one() {
+        return Err(ContractError::InvalidPortions {});
+    }
+    FEE_RECIPIENTS.save(deps.storage, &recipients)?;
+
+    Ok(Response::new().add_attribute("action", "set_fee_recipients"))
+}
+
+pub fn execute_distribute_fees(deps: DepsMut, denom: String) -> Result<Response, ContractError> {
+    let recipients = FEE_RECIPIENTS.may_load(deps.storage)?.unwrap_or_default();
+    if recipients.is_empty() {
+        return Err(ContractError::NoFeeRecipients {});
+    }
+    let accrued = ACCRUED_FEES.may_load(deps.storage, &denom)?.unwrap_or_default();
+
+    let mut distributed = Uint128::zero();
+    let mut messages = vec![];
+    for recipient in recipients {
+        let amount = accrued * recipient.portion;
+        if amount.is_zero() {
+            continue;
+        }
+        distributed += amount;
+        messages.push(payment_msg(deps.storage, &recipient.address, coin(amount.u128(), denom.clone()))?);
+    }
+    // rounding dust stays accrued for the next distribution
+    ACCRUED_FEES.save(deps.storage, &denom, &(accrued - distributed))?;
+
+    Ok(Response::new()
+        .add_attribute("action", "distribute_fees")
+        .add_attribute("denom", denom)
+        .add_attribute("amount", distributed)
+        .add_messages(messages))
+}
+
+pub fn execute_change_fee(
+    deps: DepsMut,
+    info: MessageInfo,
+    fee: Decimal,
+) -> Result<Response, ContractError> {
+    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
+        if state.fee_manager.ne(&info.sender) {
+            return Err(ContractError::Unauthorized {});
+        }
+        if fee > state.fee {
+            return Err(ContractError::FeeTimelocked {});
+        }
+
+        state.fee = fee;
+        Ok(state)
+    })?;
+
+    let res = Response::new()
+        .add_attribute("action", "change_fee")
+        .add_attribute("fee", fee.to_string());
+    Ok(res)
+}
+
+pub fn execute_propose_fee(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    fee: Decimal,
+) -> Result<Response, ContractError> {
+    let state = STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
+        if state.fee_manager.ne(&info.sender) {
+            return Err(ContractError::Unauthorized {});
+        }
+        check_fee(fee, state.max_fee)?;
+
+        let apply_after = env
+            .block
+            .time
+            .seconds()
+            .checked_add(state.fee_timelock)
+            .ok_or(ContractError::InvalidFeeTimelock { max: MAX_FEE_TIMELOCK })?;
+        state.pending_fee = Some(PendingFee { fee, apply_after });
+        Ok(state)
+    })?;
+    let apply_after = state.pending_fee.map(|pending| pending.apply_after).unwrap_or_default();
+
+    Ok(Response::new()
+        .add_attribute("action", "propose_fee")
+        .add_attribute("fee", fee.to_string())
+        .add_attribute("apply_after", apply_after.to_string()))
+}
+
+pub fn execute_apply_fee(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let state = STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
+        if state.fee_manager.ne(&info.sender) {
+            return Err(ContractError::Unauthorized {});
+        }
+        let pending = state.pending_fee.take().ok_or(ContractError::NoPendingFee {})?;
+        if env.block.time.seconds() < pending.apply_after {
+            return Err(ContractError::FeeTimelocked {});
+        }
+        // the limit may have been lowered since the proposal
+        check_fee(pending.fee, state.max_fee)?;
+
+        state.fee = pending.fee;
+        Ok(state)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "apply_fee")
+        .add_attribute("fee", state.fee.to_string()))
+}
+
+pub fn execute_set_collection_fee(
+    deps: DepsMut,
+    info: MessageInfo,
+    collection: String,
+    fee: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    if state.fee_manager.ne(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let collection = deps.api.addr_validate(&collection)?;
+    match fee {
+        Some(fee) => {
+            check_fee(fee, state.max_fee)?;
+            COLLECTION_FEES.save(deps.storage, &collection, &fee)?;
+        }
+        None => COLLECTION_FEES.remove(deps.storage, &collection),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_collection_fee")
+        .add_attribute("collection", collection)
+        .add_attribute("fee", fee.map(|fee| fee.to_string()).unwrap_or_default()))
+}
+
+pub fn execute_set_fee_tiers(
+    deps: DepsMut,
+    info: MessageInfo,
+    tiers: Vec<FeeTier>,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    if state.fee_manager.ne(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    for tier in &tiers {
+        check_fee(tier.fee, state.max_fee)?;
+        if !DENOMS.has(deps.storage, &tier.min_volume.denom) {
+            return Err(ContractError::WrongDenom {});
+        }
+    }
+    FEE_TIERS.save(deps.storage, &tiers)?;
+
+    Ok(Response::new().add_attribute("action", "set_fee_tiers"))
+}
+
+pub fn execute_set_fee_limits(
+    deps: DepsMut,
+    info: MessageInfo,
+    max_fee: Decimal,
+    fee_timelock: u64,
+) -> Result<Response, ContractError> {
+    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
+        if state.owner.ne(&info.sender) {
+            return Err(ContractError::Unauthorized {});
+        }
+        check_fee(state.fee, max_fee)?;
+        check_fee_timelock(fee_timelock)?;
+
+        state.max_fee = max_fee;
+        state.fee_timelock = fee_timelock;
+        Ok(state)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_fee_limits")
+        .add_attribute("max_fee", max_fee.to_string())
+        .add_attribute("fee_timelock", fee_timelock.to_string()))
+}
+
+/// A fee of one or more would leave nothing of the price for the seller.
+pub(crate) fn check_fee(fee: Decimal, max_fee: Decimal) -> Result<(), ContractError> {
+    if max_fee >= Decimal::one() || fee > max_fee {
+        return Err(ContractError::InvalidFee {});
+    }
+    Ok(())
+}
+
+fn check_fee_timelock(fee_timelock: u64) -> Result<(), ContractError> {
+    if fee_timelock > MAX_FEE_TIMELOCK {
+        return Err(ContractError::InvalidFeeTimelock { max: MAX_FEE_TIMELOCK });
+    }
+    Ok(())
+}
+
+pub fn execute_transfer_ownership(
+    deps: DepsMut,
+    info: MessageInfo,
+    new_owner: String,
+) -> Result<Response, ContractError> {
+    let new_owner = deps.api.addr_validate(&new_owner)?;
+    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
+        if state.owner.ne(&info.sender) {
+            return Err(ContractError::Unauthorized {});
+        }
+        state.pending_owner = Some(new_owner.clone());
+        Ok(state)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "transfer_ownership")
+        .add_attribute("pending_owner", new_owner))
+}
+
+pub fn execute_accept_ownership(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
+        if state.pending_owner.as_ref() != Some(&info.sender) {
+            return Err(ContractError::Unauthorized {});
+        }
+        state.owner = info.sender.clone();
+        state.pending_owner = None;
+        Ok(state)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "accept_ownership")
+        .add_attribute("owner", info.sender))
+}
+
+pub fn execute_set_roles(
+    deps: DepsMut,
+    info: MessageInfo,
+    fee_manager: String,
+    pauser: String,
+) -> Result<Response, ContractError> {
+    let fee_manager = deps.api.addr_validate(&fee_manager)?;
+    let pauser = deps.api.addr_validate(&pauser)?;
+    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
+        if state.owner.ne(&info.sender) {
+            return Err(ContractError::Unauthorized {});
+        }
+        state.fee_manager = fee_manager.clone();
+        state.pauser = pauser.clone();
+        Ok(state)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_roles")
+        .add_attribute("fee_manager", fee_manager)
+        .add_attribute("pauser", pauser))
+}
+
+pub fn execute_set_paused(
+    deps: DepsMut,
+    info: MessageInfo,
+    paused: bool,
+) -> Result<Response, ContractError> {
+    let migrating = MIGRATION.may_load(deps.storage)?.is_some();
+    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
+        if state.pauser.ne(&info.sender) && state.owner.ne(&info.sender) {
+            return Err(ContractError::Unauthorized {});
+        }
+        if !paused && migrating {
+            return Err(ContractError::MigrationPending {});
+        }
+        state.paused = paused;
+        Ok(state)
+    })?;
+
+    let action = if paused { "pause" } else { "unpause" };
+    Ok(Response::new().add_attribute("action", action))
+}
+
+pub fn execute_migrate_batch(deps: DepsMut, limit: Option<u32>) -> Result<Response, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_BATCH).min(MAX_BATCH);
+    let done = migrate_batch_v0_1(deps.storage, limit)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate_batch")
+        .add_attribute("done", done.to_string()))
+}
+
+pub fn execute_add_denom(
+    deps: DepsMut,
+    info: MessageInfo,
+    denom: String,
+    kind: DenomKind,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    if state.owner.ne(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let denom = match kind {
+        DenomKind::Native => denom,
+        DenomKind::Cw20 => deps.api.addr_validate(&denom)?.into_string(),
+    };
+    // payouts of open listings depend on the kind, so it can never change
+    if let Some(existing) = DENOMS.may_load(deps.storage, &denom)? {
+        if existing.kind != kind {
+            return Err(ContractError::WrongDenom {});
+        }
+    }
+    DENOMS.save(deps.storage, &denom, &DenomInfo { kind, accepted: true })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "add_denom")
+        .add_attribute("denom", denom))
+}
+
+pub fn execute_remove_denom(
+    deps: DepsMut,
+    info: MessageInfo,
+    denom: String,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    if state.owner.ne(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    DENOMS.update(deps.storage, &denom, |denom_info| -> Result<_, ContractError> {
+        let mut denom_info = denom_info.ok_or(ContractError::WrongDenom {})?;
+        denom_info.accepted = false;
+        Ok(denom_info)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_denom")
+        .add_attribute("denom", denom))
+}
+
+/// only admin. Lists the collection even if the factory does not know it.
+pub fn execute_add_collection(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    set_collection_access(deps, info, address, true)?;
+    Ok(Response::new().add_attribute("action", "add_collection"))
+}
+
+/// only admin. Blocks listings and offers, also for factory collections.
+pub fn execute_deny_collection(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    set_collection_access(deps, info, address, false)?;
+    Ok(Response::new().add_attribute("action", "deny_collection"))
+}
+
+fn set_collection_access(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+    allowed: bool,
+) -> Result<(), ContractError> {
+    let state = STATE.load(deps.storage)?;
+    if state.owner.ne(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let address = deps.api.addr_validate(&address)?;
+    COLLECTION_ACCESS.save(deps.storage, &address, &allowed)?;
+    Ok(())
+}
+
+pub fn execute_set_royalty_support(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+    supported: bool,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    if state.owner.ne(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let address = deps.api.addr_validate(&address)?;
+    if supported {
+        ROYALTY_COLLECTIONS.save(deps.storage, &address, &true)?;
+    } else {
+        ROYALTY_COLLECTIONS.remove(deps.storage, &address);
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_royalty_support")
+        .add_attribute("collection", address)
+        .add_attribute("supported", supported.to_string()))
+}
+
+pub fn execute_set_factory(
+    deps: DepsMut,
+    info: MessageInfo,
+    factory: Option<String>,
+) -> Result<Response, ContractError> {
+    let factory = maybe_addr(deps.api, factory)?;
+    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
+        if state.owner.ne(&info.sender) {
+            return Err(ContractError::Unauthorized {});
+        }
+        state.factory = factory.clone();
+        Ok(state)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_factory")
+        .add_attribute("factory", factory.map(String::from).unwrap_or_default()))
+}
+
+/// A collection can be traded if the admin added it, or if it was not denied and
+/// the factory created it. Without a factory, every collection not denied is listed.
+pub fn is_registered_collection(deps: Deps, address: &Addr) -> Result<(), ContractError> {
+    let registered = match COLLECTION_ACCESS.may_load(deps.storage, address)? {
+        Some(allowed) => allowed,
+        None => match STATE.load(deps.storage)?.factory {
+            Some(factory) => deps.querier.query_wasm_smart(
+                factory,
+                &FactoryQueryMsg::CheckCollection {
+                    address: address.to_string(),
+                },
+            )?,
+            None => true,
+        },
+    };
+
+    if !registered {
+        return Err(ContractError::NotListedNFt {});
+    }
+    Ok(())
+}
+
+/// Splits `price` between the marketplace fee (kept by the contract), the collection
+/// royalty and the seller. Returns the payout messages, the royalty and the fee amount.
+fn sale_payouts(
+    deps: Deps,
+    contract: &Addr,
+    token_id: &str,
+    seller: &Addr,
+    price: &Coin,
+) -> Result<(Vec<CosmosMsg>, Uint128, Uint128), ContractError> {
+    let fee = sale_fee(deps, contract, seller, &price.denom)?;
+    let fee_amount = price.amount * fee;
+
+    // royalty is paid out of the seller's proceeds, same as the marketplace fee,
+    // and capped at what is left after the fee so a collection cannot block sales
+    let royalty = query_royalty(deps, contract, token_id, price.amount)?;
+    let royalty_amount = royalty
+        .as_ref()
+        .map(|royalty| royalty.royalty_amount.min(price.amount.saturating_sub(fee_amount)))
+        .unwrap_or_default();
+    let net_amount = price
+        .amount
+        .checked_sub(fee_amount)
+        .and_then(|amount| amount.checked_sub(royalty_amount))
+        .map_err(StdError::from)?;
+
+    // a capped royalty can take all of it, and zero transfers are rejected
+    let mut messages = vec![];
+    if !net_amount.is_zero() {
+        messages.push(payment_msg(
+            deps.storage,
+            seller.as_str(),
+            coin(net_amount.u128(), price.denom.clone()),
+        )?);
+    }
+
+    if let Some(royalty) = royalty.filter(|royalty| !royalty.royalty_amount.is_zero()) {
+        messages.push(payment_msg(
+            deps.storage,
+            &royalty.address,
+            coin(royalty_amount.u128(), price.denom.clone()),
+        )?);
+    }
+
+    Ok((messages, royalty_amount, fee_amount))
+}
+
+/// Lowest of the marketplace fee, the collection's fee and the seller's fee tiers.
+fn sale_fee(deps: Deps, contract: &Addr, seller: &Addr, denom: &str) -> StdResult<Decimal> {
+    let mut fee = STATE.load(deps.storage)?.fee;
+    if let Some(collection_fee) = COLLECTION_FEES.may_load(deps.storage, contract)? {
+        fee = fee.min(collection_fee);
+    }
+
+    let volume = SELLER_VOLUME
+        .may_load(deps.storage, (seller.as_str(), denom))?
+        .unwrap_or_default();
+    let tiers = FEE_TIERS.may_load(deps.storage)?.unwrap_or_default();
+    for tier in tiers {
+        if tier.min_volume.denom == denom && volume >= tier.min_volume.amount {
+            fee = fee.min(tier.fee);
+        }
+    }
+    Ok(fee)
+}
+
+/// Adds a completed sale to the global and per-collection tvl, the accrued fees and
+/// the sale history.
+fn record_sale(storage: &mut dyn Storage, sale: SaleHistoryInfo) -> StdResult<()> {
+    ACCRUED_FEES.update(storage, &sale.denom, |fees| -> StdResult<_> {
+        Ok(fees.unwrap_or_default() + sale.fee_amount)
+    })?;
+    SELLER_VOLUME.update(storage, (&sale.from, &sale.denom), |volume| -> StdResult<_> {
+        Ok(volume.unwrap_or_default() + sale.amount)
+    })?;
+
+    let volume = coin(sale.amount.u128(), sale.denom.clone());
+    STATE.update(storage, |mut state| -> StdResult<_> {
+        add_coin(&mut state.tvl, &volume);
+        Ok(state)
+    })?;
+
+    let mut collection_info = COLLECTIONINFO
+        .may_load(storage, &sale.nft_address)?
+        .unwrap_or(CollectionInfo {
+            sale_id: 0,
+            tvl: vec![],
+            num_offerings: 0,
+        });
+    collection_info.sale_id += 1;
+    add_coin(&mut collection_info.tvl, &volume);
+    COLLECTIONINFO.save(storage, &sale.nft_address, &collection_info)?;
+    record_sale_stats(storage, &sale, &volume)?;
+
+    let id = next_sale_id(storage)?;
+    sales().save(storage, id, &sale)
+}
+
+/// Updates the aggregates behind the `CollectionStats` query.
+pub(crate) fn record_sale_stats(storage: &mut dyn Storage, sale: &SaleHistoryInfo, price: &Coin) -> StdResult<()> {
+    let collection = sale.nft_address.as_str();
+    let mut stats = SALE_STATS.may_load(storage, collection)?.unwrap_or_default();
+    stats.last_sale_price = Some(price.clone());
+    for trader in [&sale.from, &sale.to].iter() {
+        if !TRADERS.has(storage, (collection, trader.as_str())) {
+            TRADERS.save(storage, (collection, trader.as_str()), &true)?;
+            stats.unique_traders += 1;
+        }
+    }
+    match stats.volumes.iter_mut().find(|volume| volume.denom == price.denom) {
+        Some(volume) => {
+            volume.volume += price.amount;
+            volume.sales += 1;
+        }
+        None => stats.volumes.push(DenomVolume {
+            denom: price.denom.clone(),
+            volume: price.amount,
+            sales: 1,
+        }),
+    }
+    SALE_STATS.save(storage, collection, &stats)?;
+
+    let bucket = sale.time / VOLUME_BUCKET_SECONDS;
+    VOLUME_BUCKETS.update(storage, (collection, bucket), |volume| -> StdResult<_> {
+        let mut volume = volume.unwrap_or_default();
+        add_coin(&mut volume, price);
+        Ok(volume)
+    })?;
+    Ok(())
+}
+
+pub(crate) fn check_denom(storage: &dyn Storage, denom: &str) -> Result<(), ContractError> {
+    match DENOMS.may_load(storage, denom)? {
+        Some(denom_info) if denom_info.accepted => Ok(()),
+        _ => Err(ContractError::WrongDenom {}),
+    }
+}
+
+/// Native funds can not stand in for a CW20 token whose address they share as denom.
+fn native_funds(storage: &dyn Storage, funds: Vec<Coin>) -> Result<Vec<Coin>, ContractError> {
+    for fund in &funds {
+        if let Some(DenomInfo { kind: DenomKind::Cw20, .. }) = DENOMS.may_load(storage, &fund.denom)? {
+            return Err(ContractError::WrongDenom {});
+        }
+    }
+    Ok(funds)
+}
+
+/// Pays `amount` to `recipient` with a bank send, or a token transfer for CW20 denoms.
+pub(crate) fn payment_msg(storage: &dyn Storage, recipient: &str, amount: Coin) -> StdResult<CosmosMsg> {
+    let kind = DENOMS.may_load(storage, &amount.denom)?.map(|denom_info| denom_info.kind);
+    Ok(match kind {
+        Some(DenomKind::Cw20) => WasmMsg::Execute {
+            contract_addr: amount.denom,
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: recipient.to_string(),
+                amount: amount.amount,
+            })?,
+            funds: vec![],
+        }
+        .into(),
+        _ => BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: vec![amount],
+        }
+        .into(),
+    })
+}
+
+pub(crate) fn transfer_nft_msg(contract: &Addr, recipient: &str, token_id: &str) -> StdResult<CosmosMsg> {
+    Ok(WasmMsg::Execute {
+        contract_addr: contract.to_string(),
+        msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+            recipient: recipient.to_string(),
+            token_id: token_id.to_string(),
+        })?,
+        funds: vec![],
+    }
+    .into())
+}
+
+pub(crate) fn query_nft_owner(
+    deps: Deps,
+    collection: &Addr,
+    token_id: &str,
+) -> StdResult<OwnerOfResponse> {
+    deps.querier.query_wasm_smart(
+        collection,
+        &Cw721QueryMsg::OwnerOf {
+            token_id: token_id.to_string(),
+            include_expired: None,
+        },
+    )
+}
+
+/// Only collections the admin registered are asked for their royalty, older
+/// ones cannot answer. Once registered, a failing query aborts the sale so a
+/// broken collection never skips its creator.
+fn query_royalty(
+    deps: Deps,
+    contract: &Addr,
+    token_id: &str,
+    sale_price: Uint128,
+) -> StdResult<Option<RoyaltiesInfoResponse>> {
+    if !ROYALTY_COLLECTIONS.has(deps.storage, contract) {
+        return Ok(None);
+    }
+    let msg = Cw721QueryMsg::RoyaltyInfo {
+        token_id: token_id.to_string(),
+        sale_price,
+    };
+    deps.querier.query_wasm_smart(contract, &msg).map(Some)
+}
+
+#[entry_point]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::GetStateInfo{} => to_binary(&query_state(deps)?),
+        QueryMsg::GetCollectionInfo { address } => to_binary(&query_collection_info(deps,address)?),
+        QueryMsg::CollectionStats { address } => {
+            to_binary(&query_collection_stats(deps, env, address)?)
+        }
+        QueryMsg::GetFee {} => to_binary(&query_fee(deps)?),
+        QueryMsg::GetCollectionFee { collection } => {
+            let collection = deps.api.addr_validate(&collection)?;
+            to_binary(&COLLECTION_FEES.may_load(deps.storage, &collection)?)
+        }
+        QueryMsg::GetFeeTiers {} => {
+            to_binary(&FEE_TIERS.may_load(deps.storage)?.unwrap_or_default())
+        }
+        QueryMsg::GetSellerVolume { seller } => to_binary(&query_seller_volume(deps, seller)?),
+        QueryMsg::CheckRoyaltySupport { address } => {
+            let address = deps.api.addr_validate(&address)?;
+            to_binary(&ROYALTY_COLLECTIONS.has(deps.storage, &address))
+        }
+        QueryMsg::CheckCollection { address } => {
+            to_binary(&query_check_collection(deps, address)?)
+        }
+        QueryMsg::GetDenoms {} => to_binary(&query_denoms(deps)?),
+        QueryMsg::GetAccruedFees {} => to_binary(&query_accrued_fees(deps)?),
+        QueryMsg::GetFeeRecipients {} => {
+            to_binary(&FEE_RECIPIENTS.may_load(deps.storage)?.unwrap_or_default())
+        }
+        QueryMsg::GetOffers { address, include_expired, start_after, limit } => to_binary(
+            &query_all(deps, env, address, include_expired.unwrap_or(false), start_after, limit)?,
+        ),
+        QueryMsg::GetOffersBySeller { seller, include_expired, start_after, limit } => {
+            to_binary(&query_offers_by_seller(
+                deps,
+                env,
+                seller,
+                include_expired.unwrap_or(false),
+                start_after,
+                limit,
+            )?)
+        }
+        QueryMsg::GetOffersByPrice { address, denom, include_expired, start_after, limit } => {
+            to_binary(&query_offers_by_price(
+                deps,
+                env,
+                address,
+                denom,
+                include_expired.unwrap_or(false),
+                start_after,
+                limit,
+            )?)
+        }
+        QueryMsg::GetOffersReservedFor { buyer, include_expired, start_after, limit } => {
+            to_binary(&query_offers_reserved_for(
+                deps,
+                env,
+                buyer,
+                include_expired.unwrap_or(false),
+                start_after,
+                limit,
+            )?)
+        }
+        QueryMsg::GetOffering { offering_id } => to_binary(&query_offering(deps, offering_id)?),
+        QueryMsg::IsListingValid { offering_id } => {
+            to_binary(&query_is_listing_valid(deps, env, offering_id)?)
+        }
+        QueryMsg::GetSaleHistory { page_num, count,address }  => {
+            to_binary(&query_sale_history(deps,  page_num, count,address )?)
+        }
+        QueryMsg::GetSalesByAddress { address, start_after, limit } => {
+            to_binary(&query_sales_by_address(deps, address, start_after, limit)?)
+        }
+        QueryMsg::GetTokenSaleHistory { collection, token_id, start_after, limit } => {
+            to_binary(&query_token_sale_history(deps, collection, token_id, start_after, limit)?)
+        }
+        QueryMsg::GetAuctions { address, start_after, limit } => {
+            to_binary(&query_auctions(deps, address, start_after, limit)?)
+        }
+        QueryMsg::GetAuction { address, auction_id } => {
+            to_binary(&query_auction(deps, address, auction_id)?)
+        }
+        QueryMsg::GetBuyOffersByCollection { collection, start_after, limit } => {
+            to_binary(&query_buy_offers_by_collection(deps, collection, start_after, limit)?)
+        }
+        QueryMsg::GetBuyOffersByToken { collection, token_id, start_after, limit } => to_binary(
+            &query_buy_offers_by_token(deps, collection, token_id, start_after, limit)?,
+        ),
+        QueryMsg::GetBuyOffersByBidder { bidder, start_after, limit } => {
+            to_binary(&query_buy_offers_by_bidder(deps, bidder, start_after, limit)?)
+        }
+        QueryMsg::GetTrade { trade_id } => to_binary(&query_trade(deps, trade_id)?),
+        QueryMsg::GetTradesByProposer { proposer, start_after, limit } => {
+            to_binary(&query_trades_by_proposer(deps, proposer, start_after, limit)?)
+        }
+        QueryMsg::GetTradesByCounterparty { counterparty, start_after, limit } => {
+            to_binary(&query_trades_by_counterparty(deps, counterparty, start_after, limit)?)
+        }
+    }
+}
+
+fn query_state(deps: Deps) -> StdResult<State> {
+    let state = STATE.load(deps.storage)?;
+    Ok(state)
+}
+
+fn query_collection_info(deps: Deps, address: String) -> StdResult<CollectionInfo>{
+    COLLECTIONINFO.load(deps.storage, &address)
+}
+
+fn query_collection_stats(
+    deps: Deps,
+    env: Env,
+    address: String,
+) -> StdResult<CollectionStatsResponse> {
+    let collection = deps.api.addr_validate(&address)?;
+    let stats = SALE_STATS.may_load(deps.storage, collection.as_str())?.unwrap_or_default();
+
+    // the first public, unexpired listing of each denom. The scan of a denom is
+    // bounded so the query stays cheap on large collections.
+    let mut floor_price: Vec<Coin> = vec![];
+    for denom in DENOMS.keys(deps.storage, None, None, Order::Ascending) {
+        let denom = denom?;
+        let listings = offerings()
+            .idx
+            .price
+            .sub_prefix((collection.clone(), denom))
+            .range(deps.storage, None, None, Order::Ascending)
+            .take(FLOOR_SCAN_LIMIT);
+        for item in listings {
+            let (_, off) = item?;
+            if off.reserved_for.is_none() && !off.expires.is_expired(&env.block) {
+                floor_price.push(off.list_price);
+                break;
+            }
+        }
+    }
+
+    let current_bucket = env.block.time.seconds() / VOLUME_BUCKET_SECONDS;
+    let recent_volume = |seconds: u64| -> StdResult<Vec<Coin>> {
+        let start = (current_bucket + 1).saturating_sub(seconds / VOLUME_BUCKET_SECONDS);
+        let mut volume = vec![];
+        for item in VOLUME_BUCKETS.prefix(collection.as_str()).range(
+            deps.storage,
+            Some(Bound::inclusive(start)),
+            None,
+            Order::Ascending,
+        ) {
+            for amount in item?.1 {
+                add_coin(&mut volume, &amount);
+            }
+        }
+        Ok(volume)
+    };
+
+    Ok(CollectionStatsResponse {
+        floor_price,
+        volume_24h: recent_volume(24 * 3600)?,
+        volume_7d: recent_volume(7 * 24 * 3600)?,
+        total_volume: stats
+            .volumes
+            .iter()
+            .map(|volume| coin(volume.volume.u128(), volume.denom.clone()))
+            .collect(),
+        average_price: stats
+            .volumes
+            .iter()
+            .map(|volume| {
+                coin((volume.volume / Uint128::from(volume.sales)).u128(), volume.denom.clone())
+            })
+            .collect(),
+        last_sale_price: stats.last_sale_price,
+        num_sales: stats.volumes.iter().map(|volume| volume.sales).sum(),
+        unique_traders: stats.unique_traders,
+    })
+}
+
+fn query_check_collection(deps: Deps, address: String) -> StdResult<bool> {
+    let address = deps.api.addr_validate(&address)?;
+    Ok(is_registered_collection(deps, &address).is_ok())
+}
+
+fn query_fee(deps: Deps) -> StdResult<FeeResponse> {
+    let state = STATE.load(deps.storage)?;
+    Ok(FeeResponse {
+        fee: state.fee,
+        max_fee: state.max_fee,
+        fee_timelock: state.fee_timelock,
+        pending_fee: state.pending_fee,
+    })
+}
+
+fn query_seller_volume(deps: Deps, seller: String) -> StdResult<Vec<Coin>> {
+    let seller = deps.api.addr_validate(&seller)?;
+    SELLER_VOLUME
+        .prefix(seller.as_str())
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| coin(amount.u128(), denom)))
+        .collect()
+}
+
+fn query_denoms(deps: Deps) -> StdResult<Vec<DenomResponse>> {
+    DENOMS
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| !matches!(item, Ok((_, denom_info)) if !denom_info.accepted))
+        .map(|item| item.map(|(denom, denom_info)| DenomResponse { denom, kind: denom_info.kind }))
+        .collect()
+}
+
+fn query_accrued_fees(deps: Deps) -> StdResult<Vec<Coin>> {
+    ACCRUED_FEES
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| coin(amount.u128(), denom)))
+        .collect()
+}
+
+fn query_all(
+    deps: Deps,
+    env: Env,
+    address: String,
+    include_expired: bool,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<OfferingResult>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let collection = deps.api.addr_validate(&address)?;
+
+    let items = offerings()
+        .idx
+        .collection
+        .prefix(collection)
+        .range(deps.storage, start, None, Order::Ascending);
+    offering_page(items, &env.block, include_expired, limit)
+}
+
+fn query_offers_by_seller(
+    deps: Deps,
+    env: Env,
+    seller: String,
+    include_expired: bool,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<OfferingResult>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let seller = deps.api.addr_validate(&seller)?;
+
+    let items = offerings()
+        .idx
+        .seller
+        .prefix(seller)
+        .range(deps.storage, start, None, Order::Ascending);
+    offering_page(items, &env.block, include_expired, limit)
+}
+
+/// `start_after` is the `(list price amount, offering id)` of the last result.
+fn query_offers_by_price(
+    deps: Deps,
+    env: Env,
+    address: String,
+    denom: String,
+    include_expired: bool,
+    start_after: Option<(Uint128, u64)>,
+    limit: Option<u32>,
+) -> StdResult<Vec<OfferingResult>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|(price, id)| Bound::exclusive((price.u128(), id)));
+    let collection = deps.api.addr_validate(&address)?;
+
+    let items = offerings()
+        .idx
+        .price
+        .sub_prefix((collection, denom))
+        .range(deps.storage, start, None, Order::Ascending);
+    offering_page(items, &env.block, include_expired, limit)
+}
+
+fn query_offers_reserved_for(
+    deps: Deps,
+    env: Env,
+    buyer: String,
+    include_expired: bool,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<OfferingResult>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let buyer = deps.api.addr_validate(&buyer)?;
+
+    let items = offerings()
+        .idx
+        .reserved_for
+        .prefix(buyer.into_string())
+        .range(deps.storage, start, None, Order::Ascending);
+    offering_page(items, &env.block, include_expired, limit)
+}
+
+/// Takes a page of listings, leaving out expired ones unless `include_expired` is set.
+fn offering_page(
+    items: impl Iterator<Item = StdResult<(u64, Offering)>>,
+    block: &BlockInfo,
+    include_expired: bool,
+    limit: usize,
+) -> StdResult<Vec<OfferingResult>> {
+    items
+        .filter(|item| {
+            include_expired || !matches!(item, Ok((_, off)) if off.expires.is_expired(block))
+        })
+        .take(limit)
+        .map(|item| item.map(|(id, off)| offering_result(id, off)))
+        .collect()
+}
+
+fn query_offering(deps: Deps, offering_id: u64) -> StdResult<OfferingResult> {
+    let off = offerings().load(deps.storage, offering_id)?;
+    Ok(offering_result(offering_id, off))
+}
+
+fn query_is_listing_valid(deps: Deps, env: Env, offering_id: u64) -> StdResult<bool> {
+    let off = offerings().load(deps.storage, offering_id)?;
+    Ok(match off.custody {
+        Custody::Escrow => true,
+        Custody::Approval => is_approval_valid(deps, &env, &off),
+    })
+}
+
+fn offering_result(id: u64, off: Offering) -> OfferingResult {
+    OfferingResult {
+        id,
+        token_id: off.token_id,
+        contract: off.contract,
+        seller: off.seller,
+        list_price: off.list_price,
+        image_url: off.image_url,
+        custody: off.custody,
+        expires: off.expires,
+        reserved_for: off.reserved_for,
+    }
+}
 
 fn query_sale_history(
     deps: Deps,
@@ -406,149 +2125,1755 @@ fn query_sale_history(
     address:String
 ) -> StdResult<Vec<SaleHistoryInfo>> {
     if page_num == 0 {
-        let sale_history:Vec<SaleHistoryInfo> = Vec::new(); 
-        Ok(sale_history)
-    }
-    else{
-        let mut sale_history:Vec<SaleHistoryInfo> = Vec::new(); 
-        for i in (page_num-1)*count+1 .. page_num*count+1{
-            let sale_info = SALEHISTORY.may_load(deps.storage, (&address,&i.to_string()))?;
-            if sale_info == None{
-                continue;
+        return Ok(vec![]);
+    }
+    // u32 * u32 fits in u64, but not necessarily in a wasm32 usize
+    let skip = usize::try_from(u64::from(page_num - 1) * u64::from(count)).unwrap_or(usize::MAX);
+    sales()
+        .idx
+        .collection
+        .prefix(address)
+        .range(deps.storage, None, None, Order::Ascending)
+        .skip(skip)
+        .take(count as usize)
+        .map(|item| item.map(|(_, sale)| sale))
+        .collect()
+}
+
+fn query_sales_by_address(
+    deps: Deps,
+    address: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<SaleResult>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let address = deps.api.addr_validate(&address)?.to_string();
+    let sales = sales();
+    let page = |index: &MultiIndex<String, SaleHistoryInfo, u64>| -> StdResult<Vec<_>> {
+        index
+            .prefix(address.clone())
+            .range(deps.storage, None, start_after.map(Bound::exclusive), Order::Descending)
+            .take(limit)
+            .collect()
+    };
+
+    // merge the newest sales on either side, counting a sale to oneself once
+    let mut items = page(&sales.idx.from)?;
+    items.extend(page(&sales.idx.to)?);
+    items.sort_by(|(a, _), (b, _)| b.cmp(a));
+    items.dedup_by_key(|(id, _)| *id);
+    Ok(items.into_iter().take(limit).map(|(id, sale)| sale_result(id, sale)).collect())
+}
+
+fn query_token_sale_history(
+    deps: Deps,
+    collection: String,
+    token_id: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<SaleResult>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let end = start_after.map(Bound::exclusive);
+    let collection = deps.api.addr_validate(&collection)?.to_string();
+
+    sales()
+        .idx
+        .token
+        .prefix((collection, token_id))
+        .range(deps.storage, None, end, Order::Descending)
+        .take(limit)
+        .map(|item| item.map(|(id, sale)| sale_result(id, sale)))
+        .collect()
+}
+
+fn sale_result(id: u64, sale: SaleHistoryInfo) -> SaleResult {
+    SaleResult {
+        id,
+        from: sale.from,
+        to: sale.to,
+        denom: sale.denom,
+        amount: sale.amount,
+        royalty_amount: sale.royalty_amount,
+        fee_amount: sale.fee_amount,
+        time: sale.time,
+        nft_address: sale.nft_address,
+        token_id: sale.token_id,
+    }
+}
+
+
+fn query_auction(deps: Deps, address: String, auction_id: u64) -> StdResult<AuctionResult> {
+    let auction = AUCTIONS.load(deps.storage, (&address, auction_id))?;
+    Ok(auction_result(auction_id, auction))
+}
+
+fn query_auctions(
+    deps: Deps,
+    address: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<AuctionResult>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    AUCTIONS
+        .prefix(&address)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(id, auction)| auction_result(id, auction)))
+        .collect()
+}
+
+fn auction_result(id: u64, auction: Auction) -> AuctionResult {
+    AuctionResult {
+        id,
+        token_id: auction.token_id,
+        contract: auction.contract,
+        seller: auction.seller,
+        reserve_price: auction.reserve_price,
+        min_bid_increment: auction.min_bid_increment,
+        start_time: auction.start_time,
+        end_time: auction.end_time,
+        image_url: auction.image_url,
+        highest_bid: auction.highest_bid,
+    }
+}
+
+
+fn query_buy_offers_by_collection(
+    deps: Deps,
+    collection: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<BuyOfferResult>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let collection = deps.api.addr_validate(&collection)?;
+
+    buy_offers()
+        .idx
+        .collection
+        .prefix(collection)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(id, offer)| buy_offer_result(id, offer)))
+        .collect()
+}
+
+fn query_buy_offers_by_token(
+    deps: Deps,
+    collection: String,
+    token_id: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<BuyOfferResult>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let collection = deps.api.addr_validate(&collection)?;
+
+    buy_offers()
+        .idx
+        .token
+        .prefix((collection, token_id))
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(id, offer)| buy_offer_result(id, offer)))
+        .collect()
+}
+
+fn query_buy_offers_by_bidder(
+    deps: Deps,
+    bidder: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<BuyOfferResult>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let bidder = deps.api.addr_validate(&bidder)?;
+
+    buy_offers()
+        .idx
+        .bidder
+        .prefix(bidder)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(id, offer)| buy_offer_result(id, offer)))
+        .collect()
+}
+
+fn buy_offer_result(id: u64, offer: BuyOffer) -> BuyOfferResult {
+    BuyOfferResult {
+        id,
+        bidder: offer.bidder,
+        collection: offer.collection,
+        token_id: offer.token_id,
+        price: offer.price,
+        expires: offer.expires,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cw721::Approval;
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_dependencies_with_balance, mock_env, mock_info, MockApi,
+        MockQuerier, MockStorage,
+    };
+    use cosmwasm_std::{
+        attr, coins, ContractResult, Decimal, OwnedDeps, SubMsg, SystemError, SystemResult,
+        WasmQuery,
+    };
+
+    /// What a collection without `RoyaltyInfo` answers: it cannot parse the query.
+    fn no_royalty() -> SystemResult<ContractResult<Binary>> {
+        SystemResult::Ok(ContractResult::Err(
+            "Error parsing into type cw721_base::msg::QueryMsg: unknown variant `royalty_info`"
+                .to_string(),
+        ))
+    }
+
+    /// Every contract is a collection without royalty support.
+    fn mock_deps() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+        let mut deps = mock_dependencies();
+        deps.querier.update_wasm(|_| no_royalty());
+        deps
+    }
+
+    fn setup(deps: DepsMut) {
+        let msg = InstantiateMsg {
+            fee: Decimal::percent(2),
+            max_fee: Decimal::percent(10),
+            fee_timelock: 86400,
+            denom:"earth".to_string(),
+            factory: None,
+        };
+        let info = mock_info("creator", &[]);
+
+        // we can just call .unwrap() to assert this was a success
+        instantiate(deps, mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn proper_initialization() {
+        let mut deps = mock_deps();
+
+        let msg = InstantiateMsg {
+            fee: Decimal::percent(2),
+            max_fee: Decimal::percent(10),
+            fee_timelock: 86400,
+            denom:"earth".to_string(),
+            factory: None,
+        };
+        let info = mock_info("creator", &[]);
+
+        // we can just call .unwrap() to assert this was a success
+        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+    }
+
+    #[test]
+    fn sell_nft() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let sell_msg = SellNft {
+            list_price: coin(1000, "earth"),
+            image_url:"image".to_string(),
+            expires: None,
+            reserved_for: None,
+        };
+
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: "1".into(),
+            sender: "owner".into(),
+            msg: to_binary(&sell_msg).unwrap(),
+        });
+        let info = mock_info("nft-collectibles", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        assert_eq!(0, res.messages.len());
+
+       
+    }
+
+    #[test]
+    fn buy_nft() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let sell_msg = SellNft {
+            list_price: coin(1000, "earth"),
+            image_url:"image".to_string(),
+            expires: None,
+            reserved_for: None,
+        };
+
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: "1".into(),
+            sender: "owner".into(),
+            msg: to_binary(&sell_msg).unwrap(),
+        });
+        let info = mock_info("collection1", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        let collection_info = query_collection_info(deps.as_ref(), "collection1".to_string()).unwrap();
+        assert_eq!(collection_info,CollectionInfo{
+            sale_id:0,
+            num_offerings:1,
+            tvl:vec![]
+        });
+
+        let offerings = query_all(deps.as_ref(), mock_env(), "collection1".to_string(), false, None, None).unwrap();
+        assert_eq!(offerings.len(),1);
+
+        let msg = ExecuteMsg::Buy {
+            offering_id: 1,
+        };
+        let info = mock_info("owner1", &coins(1000, "earth"));
+        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+       
+        let offerings = query_all(deps.as_ref(), mock_env(), "collection1".to_string(), false, None, None).unwrap();
+        assert_eq!(offerings.len(),0);
+
+         let collection_info = query_collection_info(deps.as_ref(), "collection1".to_string()).unwrap();
+        assert_eq!(collection_info,CollectionInfo{
+            sale_id:1,
+            num_offerings:0,
+            tvl:coins(1000, "earth")
+        });
+
+         let sale_history = query_sale_history(deps.as_ref(),1,10, "collection1".to_string()).unwrap();
+        assert_eq!(sale_history,vec![SaleHistoryInfo{
+            from:"owner".to_string(),
+            to:"owner1".to_string(),
+            denom:"earth".to_string(),
+            amount:Uint128::new(1000),
+            royalty_amount:Uint128::zero(),
+            fee_amount:Uint128::new(20),
+            time:mock_env().block.time.seconds(),
+            nft_address:"collection1".to_string(),
+            token_id:"1".to_string()
+        }]);
+    }
+
+    fn list_nft(deps: DepsMut, collection: &str, token_id: &str, seller: &str, price: u128) {
+        let sell_msg = SellNft {
+            list_price: coin(price, "earth"),
+            image_url:"image".to_string(),
+            expires: None,
+            reserved_for: None,
+        };
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: token_id.into(),
+            sender: seller.into(),
+            msg: to_binary(&sell_msg).unwrap(),
+        });
+        execute(deps, mock_env(), mock_info(collection, &[]), msg).unwrap();
+    }
+
+    #[test]
+    fn collection_stats() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        list_nft(deps.as_mut(), "collection1", "1", "owner", 3000);
+        list_nft(deps.as_mut(), "collection1", "2", "owner", 1000);
+        list_nft(deps.as_mut(), "collection1", "3", "owner2", 2000);
+
+        let stats = query_collection_stats(deps.as_ref(), mock_env(), "collection1".to_string()).unwrap();
+        assert_eq!(stats.floor_price, coins(1000, "earth"));
+        assert_eq!(stats.num_sales, 0);
+        assert!(stats.volume_24h.is_empty());
+
+        let msg = ExecuteMsg::Buy { offering_id: 2 };
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(1000, "earth")), msg).unwrap();
+
+        let mut later = mock_env();
+        later.block.time = later.block.time.plus_seconds(2 * 24 * 3600);
+        let msg = ExecuteMsg::Buy { offering_id: 3 };
+        execute(deps.as_mut(), later.clone(), mock_info("buyer", &coins(2000, "earth")), msg).unwrap();
+
+        let stats = query_collection_stats(deps.as_ref(), later, "collection1".to_string()).unwrap();
+        assert_eq!(stats.floor_price, coins(3000, "earth"));
+        assert_eq!(stats.volume_24h, coins(2000, "earth"));
+        assert_eq!(stats.volume_7d, coins(3000, "earth"));
+        assert_eq!(stats.total_volume, coins(3000, "earth"));
+        assert_eq!(stats.average_price, coins(1500, "earth"));
+        assert_eq!(stats.last_sale_price, Some(coin(2000, "earth")));
+        assert_eq!(stats.num_sales, 2);
+        // owner, owner2 and buyer
+        assert_eq!(stats.unique_traders, 3);
+    }
+
+    #[test]
+    fn floor_price_per_denom() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+        let msg = ExecuteMsg::AddDenom {
+            denom: "mars".to_string(),
+            kind: DenomKind::Native,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let sell_msg = SellNft {
+            list_price: coin(5000, "mars"),
+            image_url: "image".to_string(),
+            expires: None,
+            reserved_for: None,
+        };
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: "mars".into(),
+            sender: "owner".into(),
+            msg: to_binary(&sell_msg).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("collection1", &[]), msg).unwrap();
+        let stats = query_collection_stats(deps.as_ref(), mock_env(), "collection1".to_string()).unwrap();
+        assert_eq!(stats.floor_price, coins(5000, "mars"));
+
+        // many smaller amounts in another denom do not hide the mars floor
+        for price in 1..=FLOOR_SCAN_LIMIT as u128 {
+            list_nft(deps.as_mut(), "collection1", &price.to_string(), "owner", price);
+        }
+        let stats = query_collection_stats(deps.as_ref(), mock_env(), "collection1".to_string()).unwrap();
+        assert_eq!(stats.floor_price, vec![coin(1, "earth"), coin(5000, "mars")]);
+    }
+
+    #[test]
+    fn sales_by_address_and_token() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        list_nft(deps.as_mut(), "collection1", "1", "alice", 1000);
+        list_nft(deps.as_mut(), "collection1", "2", "alice", 2000);
+        let msg = ExecuteMsg::Buy { offering_id: 1 };
+        execute(deps.as_mut(), mock_env(), mock_info("bob", &coins(1000, "earth")), msg).unwrap();
+        let msg = ExecuteMsg::Buy { offering_id: 2 };
+        execute(deps.as_mut(), mock_env(), mock_info("carol", &coins(2000, "earth")), msg).unwrap();
+
+        // bob resells token 1 to alice
+        list_nft(deps.as_mut(), "collection1", "1", "bob", 1500);
+        let msg = ExecuteMsg::Buy { offering_id: 3 };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &coins(1500, "earth")), msg).unwrap();
+
+        let sales = query_sales_by_address(deps.as_ref(), "alice".to_string(), None, None).unwrap();
+        assert_eq!(sales.iter().map(|sale| sale.id).collect::<Vec<_>>(), vec![3, 2, 1]);
+        let sales = query_sales_by_address(deps.as_ref(), "alice".to_string(), Some(3), Some(1)).unwrap();
+        assert_eq!(sales.len(), 1);
+        assert_eq!(sales[0].id, 2);
+        assert_eq!(sales[0].to, "carol");
+
+        let provenance =
+            query_token_sale_history(deps.as_ref(), "collection1".to_string(), "1".to_string(), None, None)
+                .unwrap();
+        assert_eq!(provenance.len(), 2);
+        assert_eq!((provenance[0].from.as_str(), provenance[0].to.as_str()), ("bob", "alice"));
+        assert_eq!((provenance[1].from.as_str(), provenance[1].to.as_str()), ("alice", "bob"));
+
+        let sale_history = query_sale_history(deps.as_ref(), 2, 2, "collection1".to_string()).unwrap();
+        assert_eq!(sale_history.len(), 1);
+        assert_eq!(sale_history[0].amount, Uint128::new(1500));
+        // pages far past the end are empty rather than overflowing
+        let sale_history =
+            query_sale_history(deps.as_ref(), u32::MAX, u32::MAX, "collection1".to_string()).unwrap();
+        assert!(sale_history.is_empty());
+    }
+
+    #[test]
+    fn two_step_ownership_and_roles() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let msg = ExecuteMsg::TransferOwnership { new_owner: "new_owner".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        // ownership only moves once accepted
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), ExecuteMsg::AcceptOwnership {})
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        assert_eq!(STATE.load(&deps.storage).unwrap().owner, "creator");
+        execute(deps.as_mut(), mock_env(), mock_info("new_owner", &[]), ExecuteMsg::AcceptOwnership {}).unwrap();
+        let state = STATE.load(&deps.storage).unwrap();
+        assert_eq!(state.owner, "new_owner");
+        assert_eq!(state.pending_owner, None);
+
+        let msg = ExecuteMsg::SetRoles {
+            fee_manager: "treasury".to_string(),
+            pauser: "guardian".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        execute(deps.as_mut(), mock_env(), mock_info("new_owner", &[]), msg).unwrap();
+
+        // the fee is the fee manager's business, not the owner's
+        let msg = ExecuteMsg::ChangeFee { fee: Decimal::percent(1) };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("new_owner", &[]), msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        execute(deps.as_mut(), mock_env(), mock_info("treasury", &[]), msg).unwrap();
+        assert_eq!(query_fee(deps.as_ref()).unwrap().fee, Decimal::percent(1));
+    }
+
+    #[test]
+    fn pause_blocks_trading_but_not_withdrawals() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+        list_nft(deps.as_mut(), "collection1", "1", "owner", 1000);
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), ExecuteMsg::Pause {})
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), ExecuteMsg::Pause {}).unwrap();
+
+        let msg = ExecuteMsg::Buy { offering_id: 1 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(1000, "earth")), msg)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Paused {}));
+
+        let sell_msg = SellNft {
+            list_price: coin(1000, "earth"),
+            image_url: "image".to_string(),
+            expires: None,
+            reserved_for: None,
+        };
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: "2".into(),
+            sender: "owner".into(),
+            msg: to_binary(&sell_msg).unwrap(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), mock_info("collection1", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Paused {}));
+
+        let msg = ExecuteMsg::WithdrawNft { offering_id: 1 };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), ExecuteMsg::Unpause {}).unwrap();
+        list_nft(deps.as_mut(), "collection1", "1", "owner", 1000);
+    }
+
+    #[test]
+    fn collection_fees_and_fee_tiers() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let seller_payout = |res: &Response, seller: &str| match &res.messages[0].msg {
+            CosmosMsg::Bank(BankMsg::Send { to_address, amount }) if to_address == seller => {
+                amount[0].amount.u128()
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        };
+
+        let msg = ExecuteMsg::SetCollectionFee {
+            collection: "partner".to_string(),
+            fee: Some(Decimal::zero()),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::SetFeeTiers {
+            tiers: vec![FeeTier { min_volume: coin(2000, "earth"), fee: Decimal::percent(1) }],
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        list_nft(deps.as_mut(), "partner", "1", "seller", 1000);
+        list_nft(deps.as_mut(), "collection1", "1", "seller", 1000);
+        list_nft(deps.as_mut(), "collection1", "2", "seller", 1000);
+        list_nft(deps.as_mut(), "collection1", "3", "seller", 1000);
+
+        // no fee on the partner collection
+        let msg = ExecuteMsg::Buy { offering_id: 1 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(1000, "earth")), msg).unwrap();
+        assert_eq!(seller_payout(&res, "seller"), 1000);
+
+        let msg = ExecuteMsg::Buy { offering_id: 2 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(1000, "earth")), msg).unwrap();
+        assert_eq!(seller_payout(&res, "seller"), 980);
+
+        // 2000 earth sold so far reaches the discounted tier
+        let volume = query_seller_volume(deps.as_ref(), "seller".to_string()).unwrap();
+        assert_eq!(volume, coins(2000, "earth"));
+        let msg = ExecuteMsg::Buy { offering_id: 3 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(1000, "earth")), msg).unwrap();
+        assert_eq!(seller_payout(&res, "seller"), 990);
+        assert_eq!(query_accrued_fees(deps.as_ref()).unwrap(), coins(30, "earth"));
+    }
+
+    #[test]
+    fn listing_ids_are_stable() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        list_nft(deps.as_mut(), "collection1", "1", "owner", 3000);
+        list_nft(deps.as_mut(), "collection1", "2", "owner", 1000);
+        list_nft(deps.as_mut(), "collection2", "1", "owner2", 500);
+        list_nft(deps.as_mut(), "collection1", "3", "owner2", 2000);
+
+        // selling the first listing must not renumber the others
+        let msg = ExecuteMsg::Buy { offering_id: 1 };
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(3000, "earth")), msg).unwrap();
+        let err = query_offering(deps.as_ref(), 1).unwrap_err();
+        assert!(matches!(err, StdError::NotFound { .. }));
+
+        let offerings = query_all(deps.as_ref(), mock_env(), "collection1".to_string(), false, None, None).unwrap();
+        let ids: Vec<u64> = offerings.iter().map(|off| off.id).collect();
+        assert_eq!(ids, vec![2, 4]);
+        assert_eq!(offerings[0].token_id, "2".to_string());
+
+        let offerings =
+            query_all(deps.as_ref(), mock_env(), "collection1".to_string(), false, Some(2), Some(1)).unwrap();
+        assert_eq!(offerings.len(), 1);
+        assert_eq!(offerings[0].id, 4);
+
+        // withdrawing frees no id for reuse
+        let msg = ExecuteMsg::WithdrawNft { offering_id: 2 };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        list_nft(deps.as_mut(), "collection1", "2", "owner", 1500);
+        let off = query_offering(deps.as_ref(), 5).unwrap();
+        assert_eq!(off.token_id, "2".to_string());
+
+        let collection_info =
+            query_collection_info(deps.as_ref(), "collection1".to_string()).unwrap();
+        assert_eq!(collection_info.num_offerings, 2);
+
+        let offerings =
+            query_offers_by_seller(deps.as_ref(), mock_env(), "owner2".to_string(), false, None, None).unwrap();
+        let ids: Vec<u64> = offerings.iter().map(|off| off.id).collect();
+        assert_eq!(ids, vec![3, 4]);
+
+        let offerings = query_offers_by_price(
+            deps.as_ref(),
+            mock_env(),
+            "collection1".to_string(),
+            "earth".to_string(),
+            false,
+            None,
+            None,
+        )
+        .unwrap();
+        let ids: Vec<u64> = offerings.iter().map(|off| off.id).collect();
+        assert_eq!(ids, vec![5, 4]);
+        let offerings = query_offers_by_price(
+            deps.as_ref(),
+            mock_env(),
+            "collection1".to_string(),
+            "earth".to_string(),
+            false,
+            Some((Uint128::new(1500), 5)),
+            None,
+        )
+        .unwrap();
+        assert_eq!(offerings.len(), 1);
+        assert_eq!(offerings[0].id, 4);
+    }
+
+    #[test]
+    fn listing_expiration() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let env = mock_env();
+        let list = |token_id: &str, expires: Expiration| {
+            let sell_msg = SellNft {
+                list_price: coin(1000, "earth"),
+                image_url: "image".to_string(),
+                expires: Some(expires),
+                reserved_for: None,
+            };
+            ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+                token_id: token_id.into(),
+                sender: "owner".into(),
+                msg: to_binary(&sell_msg).unwrap(),
+            })
+        };
+        let info = mock_info("collection1", &[]);
+
+        let past = Expiration::AtHeight(env.block.height);
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), list("1", past)).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidExpiration {}));
+
+        let soon = Expiration::AtHeight(env.block.height + 10);
+        execute(deps.as_mut(), env.clone(), info.clone(), list("1", soon)).unwrap();
+        execute(deps.as_mut(), env.clone(), info, list("2", Expiration::Never {})).unwrap();
+
+        let mut later = mock_env();
+        later.block.height += 10;
+
+        let buy = ExecuteMsg::Buy { offering_id: 1 };
+        let err = execute(deps.as_mut(), later.clone(), mock_info("buyer", &coins(1000, "earth")), buy)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::ListingExpired {}));
+
+        let offerings =
+            query_all(deps.as_ref(), later.clone(), "collection1".to_string(), false, None, None)
+                .unwrap();
+        assert_eq!(offerings.len(), 1);
+        assert_eq!(offerings[0].id, 2);
+        let offerings =
+            query_all(deps.as_ref(), later.clone(), "collection1".to_string(), true, None, None)
+                .unwrap();
+        assert_eq!(offerings.len(), 2);
+
+        let msg = ExecuteMsg::PruneExpired {
+            collection: "collection1".to_string(),
+            start_after: None,
+            limit: None,
+        };
+        let res = execute(deps.as_mut(), later.clone(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: "collection1".to_string(),
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    recipient: "owner".to_string(),
+                    token_id: "1".to_string(),
+                })
+                .unwrap(),
+                funds: vec![],
+            })]
+        );
+        let offerings =
+            query_all(deps.as_ref(), later, "collection1".to_string(), true, None, None).unwrap();
+        assert_eq!(offerings.len(), 1);
+        let collection_info =
+            query_collection_info(deps.as_ref(), "collection1".to_string()).unwrap();
+        assert_eq!(collection_info.num_offerings, 1);
+    }
+
+    #[test]
+    fn prune_scan_is_bounded() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+        for token_id in 1..=PRUNE_SCAN_LIMIT {
+            list_nft(deps.as_mut(), "collection1", &token_id.to_string(), "owner", 1000);
+        }
+        let sell_msg = SellNft {
+            list_price: coin(1000, "earth"),
+            image_url: "image".to_string(),
+            expires: Some(Expiration::AtHeight(mock_env().block.height + 10)),
+            reserved_for: None,
+        };
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: "expiring".into(),
+            sender: "owner".into(),
+            msg: to_binary(&sell_msg).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("collection1", &[]), msg).unwrap();
+
+        let mut later = mock_env();
+        later.block.height += 10;
+        let prune = |start_after| ExecuteMsg::PruneExpired {
+            collection: "collection1".to_string(),
+            start_after,
+            limit: None,
+        };
+        // the expired listing is behind a full scan of live ones
+        let res = execute(deps.as_mut(), later.clone(), mock_info("anyone", &[]), prune(None)).unwrap();
+        assert_eq!(res.attributes[1], attr("offering_ids", ""));
+        assert_eq!(res.attributes[2], attr("last_offering_id", "100"));
+
+        let res = execute(deps.as_mut(), later.clone(), mock_info("anyone", &[]), prune(Some(100)))
+            .unwrap();
+        assert_eq!(res.attributes[1], attr("offering_ids", "101"));
+        assert_eq!(res.messages.len(), 1);
+        let res = execute(deps.as_mut(), later, mock_info("anyone", &[]), prune(Some(101))).unwrap();
+        assert_eq!(res.attributes[2], attr("last_offering_id", ""));
+    }
+
+    #[test]
+    fn reserved_sale() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let sell_msg = SellNft {
+            list_price: coin(1000, "earth"),
+            image_url: "image".to_string(),
+            expires: None,
+            reserved_for: Some("friend".to_string()),
+        };
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: "1".into(),
+            sender: "owner".into(),
+            msg: to_binary(&sell_msg).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("collection1", &[]), msg).unwrap();
+        list_nft(deps.as_mut(), "collection1", "2", "owner", 1000);
+
+        let offering = query_offering(deps.as_ref(), 1).unwrap();
+        assert_eq!(offering.reserved_for, Some(Addr::unchecked("friend")));
+        let offerings =
+            query_offers_reserved_for(deps.as_ref(), mock_env(), "friend".to_string(), false, None, None)
+                .unwrap();
+        assert_eq!(offerings.len(), 1);
+        assert_eq!(offerings[0].id, 1);
+
+        let buy = ExecuteMsg::Buy { offering_id: 1 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(1000, "earth")), buy.clone())
+            .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidBuyer {}));
+        execute(deps.as_mut(), mock_env(), mock_info("friend", &coins(1000, "earth")), buy).unwrap();
+    }
+
+    #[test]
+    fn buy_batch() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        list_nft(deps.as_mut(), "collection1", "1", "owner", 1000);
+        list_nft(deps.as_mut(), "collection1", "2", "owner", 2000);
+        list_nft(deps.as_mut(), "collection1", "3", "owner", 3000);
+
+        // listing 9 does not exist, so the strict batch fails as a whole
+        let items = vec![
+            ("collection1".to_string(), 9),
+            ("collection1".to_string(), 1),
+            ("collection1".to_string(), 2),
+        ];
+        let msg = ExecuteMsg::BuyBatch { items: items.clone(), best_effort: false };
+        let info = mock_info("buyer", &coins(3500, "earth"));
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        let offerings = query_all(deps.as_ref(), mock_env(), "collection1".to_string(), false, None, None).unwrap();
+        assert_eq!(offerings.len(), 3);
+
+        // with best effort the missing listing is skipped and the rest refunded
+        let msg = ExecuteMsg::BuyBatch { items, best_effort: true };
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.attributes[2].value, "1,2");
+        assert_eq!(res.attributes[3].value, "9");
+        assert_eq!(
+            res.messages.last().unwrap(),
+            &SubMsg::new(BankMsg::Send {
+                to_address: "buyer".into(),
+                amount: coins(500, "earth"),
+            })
+        );
+        let offerings = query_all(deps.as_ref(), mock_env(), "collection1".to_string(), false, None, None).unwrap();
+        assert_eq!(offerings.len(), 1);
+
+        // one payment has to cover every listing
+        let msg = ExecuteMsg::BuyBatch {
+            items: vec![("collection1".to_string(), 3)],
+            best_effort: false,
+        };
+        let info = mock_info("buyer", &coins(2999, "earth"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientFunds {}));
+    }
+
+    #[test]
+    fn skipped_batch_item_writes_nothing() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+        list_nft(deps.as_mut(), "collection1", "1", "owner", 1000);
+        list_nft(deps.as_mut(), "collection2", "2", "owner", 1000);
+        // collection2 fails its royalty query, after the funds were checked
+        support_royalty(deps.as_mut(), "collection2");
+        deps.querier.update_wasm(|_| {
+            SystemResult::Ok(ContractResult::Err("royalty contract is broken".to_string()))
+        });
+
+        let items = vec![("collection1".to_string(), 1), ("collection2".to_string(), 2)];
+        let msg = ExecuteMsg::BuyBatch { items, best_effort: true };
+        let info = mock_info("buyer", &coins(2000, "earth"));
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.attributes[2].value, "1");
+        assert_eq!(res.attributes[3].value, "2");
+        assert_eq!(
+            res.messages.last().unwrap(),
+            &SubMsg::new(BankMsg::Send {
+                to_address: "buyer".into(),
+                amount: coins(1000, "earth"),
+            })
+        );
+
+        offerings().load(&deps.storage, 2).unwrap();
+        let collection_info = query_collection_info(deps.as_ref(), "collection2".to_string()).unwrap();
+        assert_eq!((collection_info.sale_id, collection_info.num_offerings), (0, 1));
+        assert_eq!(STATE.load(&deps.storage).unwrap().tvl, coins(1000, "earth"));
+        let sales = query_sales_by_address(deps.as_ref(), "owner".to_string(), None, None).unwrap();
+        assert_eq!(sales.len(), 1);
+        assert_eq!(ACCRUED_FEES.load(&deps.storage, "earth").unwrap(), Uint128::new(20));
+    }
+
+    #[test]
+    fn withdraw_batch() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        list_nft(deps.as_mut(), "collection1", "1", "owner", 1000);
+        list_nft(deps.as_mut(), "collection1", "2", "owner", 1000);
+        list_nft(deps.as_mut(), "collection1", "3", "owner2", 1000);
+
+        let msg = ExecuteMsg::WithdrawBatch { offering_ids: vec![3, 1] };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let msg = ExecuteMsg::WithdrawBatch { offering_ids: vec![1, 2] };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        let offerings = query_all(deps.as_ref(), mock_env(), "collection1".to_string(), false, None, None).unwrap();
+        assert_eq!(offerings.len(), 1);
+        assert_eq!(offerings[0].id, 3);
+    }
+
+    #[test]
+    fn list_batch() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { contract_addr, msg } if contract_addr == "collection1" => {
+                match from_binary(msg).unwrap() {
+                    Cw721QueryMsg::OwnerOf { token_id, .. } => {
+                        let owner = if token_id == "3" { "someone" } else { "owner" };
+                        SystemResult::Ok(ContractResult::Ok(
+                            to_binary(&OwnerOfResponse {
+                                owner: owner.to_string(),
+                                approvals: vec![],
+                            })
+                            .unwrap(),
+                        ))
+                    }
+                    _ => no_royalty(),
+                }
+            }
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "unknown".to_string(),
+            }),
+        });
+
+        let item = |token_id: &str| ListItem {
+            token_id: token_id.to_string(),
+            list_price: coin(1000, "earth"),
+            image_url: "image".to_string(),
+            expires: None,
+            reserved_for: None,
+        };
+
+        // token 3 belongs to someone else who approved the marketplace
+        let msg = ExecuteMsg::ListBatch {
+            collection: "collection1".to_string(),
+            items: vec![item("3"), item("1")],
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let msg = ExecuteMsg::ListBatch {
+            collection: "collection1".to_string(),
+            items: vec![item("1"), item("2")],
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[1],
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: "collection1".to_string(),
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    recipient: mock_env().contract.address.to_string(),
+                    token_id: "2".to_string(),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+        let offerings =
+            query_offers_by_seller(deps.as_ref(), mock_env(), "owner".to_string(), false, None, None).unwrap();
+        assert_eq!(offerings.len(), 2);
+    }
+
+    /// Makes `collection1` report `owner` as the owner of every token, with the
+    /// marketplace approved if `approved` is set.
+    fn mock_owner_of(
+        querier: &mut MockQuerier,
+        owner: &'static str,
+        approved: bool,
+    ) {
+        querier.update_wasm(move |query| match query {
+            WasmQuery::Smart { contract_addr, msg } if contract_addr == "collection1" => {
+                match from_binary(msg).unwrap() {
+                    Cw721QueryMsg::OwnerOf { .. } => {
+                        let approvals = match approved {
+                            true => vec![Approval {
+                                spender: mock_env().contract.address.to_string(),
+                                expires: Expiration::Never {},
+                            }],
+                            false => vec![],
+                        };
+                        SystemResult::Ok(ContractResult::Ok(
+                            to_binary(&OwnerOfResponse {
+                                owner: owner.to_string(),
+                                approvals,
+                            })
+                            .unwrap(),
+                        ))
+                    }
+                    _ => no_royalty(),
+                }
             }
-            else{
-                let sale_info = sale_info.unwrap();
-                sale_history.push(sale_info); 
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "unknown".to_string(),
+            }),
+        });
+    }
+
+    #[test]
+    fn approval_listings() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let list = |token_id: &str| ExecuteMsg::ListWithApproval {
+            collection: "collection1".to_string(),
+            item: ListItem {
+                token_id: token_id.to_string(),
+                list_price: coin(1000, "earth"),
+                image_url: "image".to_string(),
+                expires: None,
+                reserved_for: None,
+            },
+        };
+
+        mock_owner_of(&mut deps.querier, "owner", false);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), list("1"))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NotApproved {}));
+
+        mock_owner_of(&mut deps.querier, "owner", true);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("thief", &[]), list("1"))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NotApproved {}));
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), list("1")).unwrap();
+        assert_eq!(res.messages.len(), 0);
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), list("2")).unwrap();
+        assert!(query_is_listing_valid(deps.as_ref(), mock_env(), 1).unwrap());
+        assert_eq!(query_offering(deps.as_ref(), 1).unwrap().custody, Custody::Approval);
+
+        // a sale transfers the NFT straight from the seller's wallet
+        let buy = ExecuteMsg::Buy { offering_id: 1 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(1000, "earth")), buy)
+            .unwrap();
+        assert_eq!(
+            res.messages.last().unwrap(),
+            &SubMsg::new(WasmMsg::Execute {
+                contract_addr: "collection1".to_string(),
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    recipient: "buyer".to_string(),
+                    token_id: "1".to_string(),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+
+        // the seller revokes the approval of the other token
+        mock_owner_of(&mut deps.querier, "owner", false);
+        assert!(!query_is_listing_valid(deps.as_ref(), mock_env(), 2).unwrap());
+        let buy = ExecuteMsg::Buy { offering_id: 2 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(1000, "earth")), buy)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::StaleListing {}));
+
+        list_nft(deps.as_mut(), "collection1", "3", "owner", 1000);
+        let msg = ExecuteMsg::PruneListings { offering_ids: vec![2, 3, 9] };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(res.attributes[1].value, "2");
+        let offerings = query_all(deps.as_ref(), mock_env(), "collection1".to_string(), false, None, None).unwrap();
+        assert_eq!(offerings.len(), 1);
+        assert_eq!(offerings[0].custody, Custody::Escrow);
+    }
+
+    #[test]
+    fn factory_collections() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { contract_addr, msg } if contract_addr == "factory" => {
+                match from_binary(msg).unwrap() {
+                    FactoryQueryMsg::CheckCollection { address } => SystemResult::Ok(
+                        ContractResult::Ok(to_binary(&(address == "collection1")).unwrap()),
+                    ),
+                }
             }
-        }
-        Ok(sale_history)
-    }
-    
-}
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "unknown".to_string(),
+            }),
+        });
 
+        // without a factory every collection is listed
+        list_nft(deps.as_mut(), "spam", "1", "owner", 1000);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cosmwasm_std::testing::{
-        mock_dependencies, mock_dependencies_with_balance, mock_env, mock_info,
-    };
-    use cosmwasm_std::{coins, Decimal, SubMsg};
+        let msg = ExecuteMsg::SetFactory { factory: Some("factory".to_string()) };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg.clone())
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
 
-    fn setup(deps: DepsMut) {
-        let msg = InstantiateMsg {
-            fee: Decimal::percent(2),
-            denom:"earth".to_string()
+        list_nft(deps.as_mut(), "collection1", "1", "owner", 1000);
+        let sell_msg = SellNft {
+            list_price: coin(1000, "earth"),
+            image_url:"image".to_string(),
+            expires: None,
+            reserved_for: None,
         };
-        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: "2".into(),
+            sender: "owner".into(),
+            msg: to_binary(&sell_msg).unwrap(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), mock_info("spam", &[]), msg.clone())
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NotListedNFt {}));
+        let offer = ExecuteMsg::MakeCollectionOffer {
+            collection: "spam".to_string(),
+            expires: Expiration::Never {},
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("bidder", &coins(10, "earth")), offer)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NotListedNFt {}));
+
+        // admin overrides win over the factory
+        let add = ExecuteMsg::AddCollection { address: "spam".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), add).unwrap();
+        assert!(query_check_collection(deps.as_ref(), "spam".to_string()).unwrap());
+        execute(deps.as_mut(), mock_env(), mock_info("spam", &[]), msg).unwrap();
+
+        let deny = ExecuteMsg::DenyCollection { address: "collection1".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), deny).unwrap();
+        assert!(!query_check_collection(deps.as_ref(), "collection1".to_string()).unwrap());
+        let sell_msg = SellNft {
+            list_price: coin(1000, "earth"),
+            image_url:"image".to_string(),
+            expires: None,
+            reserved_for: None,
+        };
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: "3".into(),
+            sender: "owner".into(),
+            msg: to_binary(&sell_msg).unwrap(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), mock_info("collection1", &[]), msg)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NotListedNFt {}));
+    }
 
-        // we can just call .unwrap() to assert this was a success
-        instantiate(deps, mock_env(), info, msg).unwrap();
+    /// `collection1` answers `RoyaltyInfo` with `rate` of the sale price for "creator1".
+    fn royalty_querier(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, rate: u64) {
+        deps.querier.update_wasm(move |query| match query {
+            WasmQuery::Smart { contract_addr, msg } if contract_addr == "collection1" => {
+                match from_binary(msg).unwrap() {
+                    Cw721QueryMsg::RoyaltyInfo { sale_price, .. } => {
+                        SystemResult::Ok(ContractResult::Ok(
+                            to_binary(&RoyaltiesInfoResponse {
+                                address: "creator1".to_string(),
+                                royalty_amount: sale_price * Decimal::percent(rate),
+                            })
+                            .unwrap(),
+                        ))
+                    }
+                    _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                        kind: "owner_of".to_string(),
+                    }),
+                }
+            }
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "unknown".to_string(),
+            }),
+        });
+    }
+
+    fn support_royalty(deps: DepsMut, collection: &str) {
+        let msg = ExecuteMsg::SetRoyaltySupport {
+            address: collection.to_string(),
+            supported: true,
+        };
+        execute(deps, mock_env(), mock_info("creator", &[]), msg).unwrap();
     }
 
     #[test]
-    fn proper_initialization() {
-        let mut deps = mock_dependencies();
+    fn failing_royalty_query_aborts_sale() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+        list_nft(deps.as_mut(), "collection1", "1", "owner", 1000);
+        list_nft(deps.as_mut(), "collection1", "2", "owner", 1000);
 
-        let msg = InstantiateMsg {
-            fee: Decimal::percent(2),
-            denom:"earth".to_string()
+        deps.querier.update_wasm(|_| {
+            SystemResult::Ok(ContractResult::Err("royalty contract is broken".to_string()))
+        });
+        // collections not registered for royalty are never asked
+        let info = mock_info("buyer", &coins(1000, "earth"));
+        let res = execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::Buy { offering_id: 1 })
+            .unwrap();
+        assert_eq!(res.attributes[4], attr("royalty_amount", "0"));
+
+        let msg = ExecuteMsg::SetRoyaltySupport {
+            address: "collection1".to_string(),
+            supported: true,
         };
-        let info = mock_info("creator", &[]);
-
-        // we can just call .unwrap() to assert this was a success
-        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        support_royalty(deps.as_mut(), "collection1");
+        let supported: bool = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::CheckRoyaltySupport { address: "collection1".to_string() },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(supported);
+
+        let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Buy { offering_id: 2 })
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::GenericErr { .. })));
     }
 
     #[test]
-    fn sell_nft() {
-        let mut deps = mock_dependencies();
+    fn buy_nft_with_royalty() {
+        let mut deps = mock_deps();
         setup(deps.as_mut());
+        royalty_querier(&mut deps, 10);
+        support_royalty(deps.as_mut(), "collection1");
 
         let sell_msg = SellNft {
             list_price: coin(1000, "earth"),
-            image_url:"image".to_string()
+            image_url:"image".to_string(),
+            expires: None,
+            reserved_for: None,
         };
-
         let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
             token_id: "1".into(),
             sender: "owner".into(),
             msg: to_binary(&sell_msg).unwrap(),
         });
-        let info = mock_info("nft-collectibles", &[]);
+        let info = mock_info("collection1", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let msg = ExecuteMsg::Buy {
+            offering_id: 1,
+        };
+        let info = mock_info("owner1", &coins(1000, "earth"));
         let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
 
+        // 2% marketplace fee stays in the contract, 10% royalty goes to the creator
+        assert_eq!(3, res.messages.len());
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "owner".into(),
+                amount: coins(880, "earth")
+            }))
+        );
+        assert_eq!(
+            res.messages[1],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "creator1".into(),
+                amount: coins(100, "earth")
+            }))
+        );
+
+        let sale_history = query_sale_history(deps.as_ref(),1,10, "collection1".to_string()).unwrap();
+        assert_eq!(sale_history[0].royalty_amount, Uint128::new(100));
+    }
+
+    #[test]
+    fn royalty_is_capped_at_the_proceeds() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+        list_nft(deps.as_mut(), "collection1", "1", "owner", 1000);
+        // the collection asks for more than the sale price
+        royalty_querier(&mut deps, 150);
+        support_royalty(deps.as_mut(), "collection1");
+
+        let info = mock_info("buyer", &coins(1000, "earth"));
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Buy { offering_id: 1 })
+            .unwrap();
+        // the fee is kept, the rest goes to the creator and nothing to the seller
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "creator1".into(),
+                amount: coins(980, "earth")
+            }))
+        );
+        assert_eq!(res.attributes[4], attr("royalty_amount", "980"));
+    }
+
+    fn start_auction(deps: DepsMut, reserve: u128, start_time: u64, end_time: u64) {
+        let msg = ReceiveNftMsg::StartAuction(StartAuction {
+            reserve_price: coin(reserve, "earth"),
+            start_time,
+            end_time,
+            min_bid_increment: Uint128::new(100),
+            image_url: "image".to_string(),
+        });
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: "1".into(),
+            sender: "owner".into(),
+            msg: to_binary(&msg).unwrap(),
+        });
+        let info = mock_info("collection1", &[]);
+        execute(deps, mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn auction_bidding() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let now = mock_env().block.time.seconds();
+        start_auction(deps.as_mut(), 1000, now + 100, now + 10_000);
+
+        let bid = ExecuteMsg::PlaceBid {
+            auction_id: 1,
+            address: "collection1".to_string(),
+        };
+
+        // bidding before the start is rejected
+        let info = mock_info("bidder1", &coins(1000, "earth"));
+        let err = execute(deps.as_mut(), mock_env(), info, bid.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::AuctionNotStarted {}));
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(200);
+
+        // below the reserve price
+        let info = mock_info("bidder1", &coins(999, "earth"));
+        let err = execute(deps.as_mut(), env.clone(), info, bid.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::BidTooLow { min } if min == Uint128::new(1000)));
+
+        // the seller cannot bid on their own auction
+        let info = mock_info("owner", &coins(1000, "earth"));
+        let err = execute(deps.as_mut(), env.clone(), info, bid.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidBuyer {}));
+
+        let info = mock_info("bidder1", &coins(1000, "earth"));
+        let res = execute(deps.as_mut(), env.clone(), info, bid.clone()).unwrap();
         assert_eq!(0, res.messages.len());
 
-       
+        // the next bid must beat the highest one by the increment
+        let info = mock_info("bidder2", &coins(1050, "earth"));
+        let err = execute(deps.as_mut(), env.clone(), info, bid.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::BidTooLow { min } if min == Uint128::new(1100)));
+
+        // outbidding refunds the previous bidder
+        let info = mock_info("bidder2", &coins(1100, "earth"));
+        let res = execute(deps.as_mut(), env.clone(), info, bid.clone()).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "bidder1".into(),
+                amount: coins(1000, "earth")
+            }))]
+        );
+
+        let auction = query_auction(deps.as_ref(), "collection1".to_string(), 1).unwrap();
+        assert_eq!(auction.end_time, now + 10_000);
+        assert_eq!(
+            auction.highest_bid,
+            Some(Bid {
+                bidder: Addr::unchecked("bidder2"),
+                amount: Uint128::new(1100),
+                time: env.block.time.seconds(),
+            })
+        );
+
+        // a bid in the final minutes extends the auction
+        env.block.time = mock_env().block.time.plus_seconds(9_900);
+        let info = mock_info("bidder1", &coins(1200, "earth"));
+        execute(deps.as_mut(), env.clone(), info, bid.clone()).unwrap();
+        let auction = query_auction(deps.as_ref(), "collection1".to_string(), 1).unwrap();
+        assert_eq!(auction.end_time, now + 9_900 + AUCTION_EXTENSION_WINDOW);
+
+        let auctions = query_auctions(deps.as_ref(), "collection1".to_string(), None, None).unwrap();
+        assert_eq!(auctions.len(), 1);
+        let auctions =
+            query_auctions(deps.as_ref(), "collection1".to_string(), Some(1), None).unwrap();
+        assert_eq!(auctions.len(), 0);
     }
 
     #[test]
-    fn buy_nft() {
-        let mut deps = mock_dependencies();
+    fn auction_settlement() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let now = mock_env().block.time.seconds();
+        start_auction(deps.as_mut(), 1000, now, now + 10_000);
+
+        let mut env = mock_env();
+        let bid = ExecuteMsg::PlaceBid {
+            auction_id: 1,
+            address: "collection1".to_string(),
+        };
+        let info = mock_info("bidder1", &coins(1000, "earth"));
+        execute(deps.as_mut(), env.clone(), info, bid).unwrap();
+
+        let settle = ExecuteMsg::SettleAuction {
+            auction_id: 1,
+            address: "collection1".to_string(),
+        };
+        let info = mock_info("anyone", &[]);
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), settle.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::AuctionNotEnded {}));
+
+        // a pause does not lock up ended auctions
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), ExecuteMsg::Pause {}).unwrap();
+        env.block.time = env.block.time.plus_seconds(10_000);
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), settle.clone()).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "owner".into(),
+                    amount: coins(980, "earth")
+                })),
+                SubMsg::new(transfer_nft_msg(&Addr::unchecked("collection1"), "bidder1", "1").unwrap()),
+            ]
+        );
+
+        let sale_history = query_sale_history(deps.as_ref(),1,10, "collection1".to_string()).unwrap();
+        assert_eq!(sale_history[0].to, "bidder1".to_string());
+        assert_eq!(sale_history[0].amount, Uint128::new(1000));
+
+        // settled auctions are gone
+        execute(deps.as_mut(), env, info, settle).unwrap_err();
+        query_auction(deps.as_ref(), "collection1".to_string(), 1).unwrap_err();
+    }
+
+    #[test]
+    fn auction_without_bids() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let now = mock_env().block.time.seconds();
+        start_auction(deps.as_mut(), 1000, now, now + 10_000);
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(10_000);
+
+        // the auction closed, no more bids
+        let bid = ExecuteMsg::PlaceBid {
+            auction_id: 1,
+            address: "collection1".to_string(),
+        };
+        let info = mock_info("bidder1", &coins(1000, "earth"));
+        let err = execute(deps.as_mut(), env.clone(), info, bid).unwrap_err();
+        assert!(matches!(err, ContractError::AuctionEnded {}));
+
+        let settle = ExecuteMsg::SettleAuction {
+            auction_id: 1,
+            address: "collection1".to_string(),
+        };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), settle).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(transfer_nft_msg(&Addr::unchecked("collection1"), "owner", "1").unwrap())]
+        );
+    }
+
+    fn accept_offer_msg(token_id: &str, offer_id: u64) -> ExecuteMsg {
+        ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: token_id.into(),
+            sender: "owner".into(),
+            msg: to_binary(&ReceiveNftMsg::AcceptOffer { offer_id }).unwrap(),
+        })
+    }
+
+    #[test]
+    fn buy_offers_flow() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let expires = Expiration::AtHeight(mock_env().block.height + 100);
+        let msg = ExecuteMsg::MakeOffer {
+            collection: "collection1".to_string(),
+            token_id: "1".to_string(),
+            expires,
+        };
+        let info = mock_info("bidder1", &coins(500, "earth"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let msg = ExecuteMsg::MakeCollectionOffer {
+            collection: "collection1".to_string(),
+            expires,
+        };
+        let info = mock_info("bidder2", &coins(400, "earth"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // offers without funds or already expired are rejected
+        let msg = ExecuteMsg::MakeCollectionOffer {
+            collection: "collection1".to_string(),
+            expires,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("bidder3", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientFunds {}));
+        let msg = ExecuteMsg::MakeCollectionOffer {
+            collection: "collection1".to_string(),
+            expires: Expiration::AtHeight(mock_env().block.height),
+        };
+        let info = mock_info("bidder3", &coins(400, "earth"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidExpiration {}));
+
+        let offers =
+            query_buy_offers_by_collection(deps.as_ref(), "collection1".to_string(), None, None)
+                .unwrap();
+        assert_eq!(offers.len(), 2);
+        let offers = query_buy_offers_by_collection(
+            deps.as_ref(),
+            "collection1".to_string(),
+            Some(1),
+            None,
+        )
+        .unwrap();
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].id, 2);
+        let offers = query_buy_offers_by_token(
+            deps.as_ref(),
+            "collection1".to_string(),
+            "1".to_string(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].bidder, Addr::unchecked("bidder1"));
+        let offers =
+            query_buy_offers_by_bidder(deps.as_ref(), "bidder2".to_string(), None, None).unwrap();
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].token_id, None);
+
+        // the token offer can only be filled by that token from that collection
+        let info = mock_info("collection1", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, accept_offer_msg("2", 1)).unwrap_err();
+        assert!(matches!(err, ContractError::OfferMismatch {}));
+        let info = mock_info("collection2", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, accept_offer_msg("1", 1)).unwrap_err();
+        assert!(matches!(err, ContractError::OfferMismatch {}));
+
+        let info = mock_info("collection1", &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, accept_offer_msg("1", 1)).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "owner".into(),
+                    amount: coins(490, "earth")
+                })),
+                SubMsg::new(transfer_nft_msg(&Addr::unchecked("collection1"), "bidder1", "1").unwrap()),
+            ]
+        );
+
+        // any token fills a collection offer, but not after it expired
+        let mut env = mock_env();
+        env.block.height += 100;
+        let info = mock_info("collection1", &[]);
+        let err = execute(deps.as_mut(), env, info.clone(), accept_offer_msg("7", 2)).unwrap_err();
+        assert!(matches!(err, ContractError::OfferExpired {}));
+        execute(deps.as_mut(), mock_env(), info, accept_offer_msg("7", 2)).unwrap();
+
+        let sale_history = query_sale_history(deps.as_ref(),1,10, "collection1".to_string()).unwrap();
+        assert_eq!(sale_history.len(), 2);
+        assert_eq!(sale_history[1].token_id, "7".to_string());
+        let offers =
+            query_buy_offers_by_collection(deps.as_ref(), "collection1".to_string(), None, None)
+                .unwrap();
+        assert_eq!(offers.len(), 0);
+    }
+
+    #[test]
+    fn cancel_buy_offer() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let msg = ExecuteMsg::MakeOffer {
+            collection: "collection1".to_string(),
+            token_id: "1".to_string(),
+            expires: Expiration::Never {},
+        };
+        let info = mock_info("bidder1", &coins(500, "earth"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let msg = ExecuteMsg::CancelOffer { offer_id: 1 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg.clone())
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("bidder1", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "bidder1".into(),
+                amount: coins(500, "earth")
+            }))]
+        );
+
+        // a cancelled offer cannot be accepted
+        let info = mock_info("collection1", &[]);
+        execute(deps.as_mut(), mock_env(), info, accept_offer_msg("1", 1)).unwrap_err();
+    }
+
+    #[test]
+    fn update_price() {
+        let mut deps = mock_deps();
         setup(deps.as_mut());
 
         let sell_msg = SellNft {
             list_price: coin(1000, "earth"),
-            image_url:"image".to_string()
+            image_url:"image".to_string(),
+            expires: None,
+            reserved_for: None,
         };
-
         let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
             token_id: "1".into(),
             sender: "owner".into(),
             msg: to_binary(&sell_msg).unwrap(),
         });
-        let info = mock_info("collection1", &[]);
-        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap();
-        assert_eq!(0, res.messages.len());
+        execute(deps.as_mut(), mock_env(), mock_info("collection1", &[]), msg).unwrap();
 
-        let collection_info = query_collection_info(deps.as_ref(), "collection1".to_string()).unwrap();
-        assert_eq!(collection_info,CollectionInfo{
-            sale_id:0,
-            num_offerings:1,
-            tvl:Uint128::zero()
-        });
+        let msg = ExecuteMsg::UpdatePrice {
+            offering_id: 1,
+            list_price: coin(1500, "earth"),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg.clone())
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
 
-        let offerings = query_all(deps.as_ref(), 1, 20, "collection1".to_string()).unwrap();
-        assert_eq!(offerings.len(),1);
+        let wrong_denom = ExecuteMsg::UpdatePrice {
+            offering_id: 1,
+            list_price: coin(1500, "mars"),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), wrong_denom)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::WrongDenom {}));
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        assert_eq!(res.events[0].ty, "price_updated");
 
+        let offerings = query_all(deps.as_ref(), mock_env(), "collection1".to_string(), false, None, None).unwrap();
+        assert_eq!(offerings[0].id, 1);
+        assert_eq!(offerings[0].list_price, coin(1500, "earth"));
+
+        // the buyer now has to pay the new price
         let msg = ExecuteMsg::Buy {
-            offering_id: "1".into(),
-            address :"collection1".to_string()
+            offering_id: 1,
         };
         let info = mock_info("owner1", &coins(1000, "earth"));
-        execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
-       
-        let offerings = query_all(deps.as_ref(), 1, 20, "collection1".to_string()).unwrap();
-        assert_eq!(offerings.len(),0);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientFunds {}));
+        let info = mock_info("owner1", &coins(1500, "earth"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
 
-         let collection_info = query_collection_info(deps.as_ref(), "collection1".to_string()).unwrap();
-        assert_eq!(collection_info,CollectionInfo{
-            sale_id:1,
-            num_offerings:0,
-            tvl:Uint128::new(1000)
+    #[test]
+    fn cw20_payments() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let msg = ExecuteMsg::AddDenom {
+            denom: "token".to_string(),
+            kind: DenomKind::Cw20,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg.clone())
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let denoms = query_denoms(deps.as_ref()).unwrap();
+        assert_eq!(denoms, vec![
+            DenomResponse { denom: "earth".to_string(), kind: DenomKind::Native },
+            DenomResponse { denom: "token".to_string(), kind: DenomKind::Cw20 },
+        ]);
+
+        let sell_msg = SellNft {
+            list_price: coin(1000, "token"),
+            image_url:"image".to_string(),
+            expires: None,
+            reserved_for: None,
+        };
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: "1".into(),
+            sender: "owner".into(),
+            msg: to_binary(&sell_msg).unwrap(),
         });
+        execute(deps.as_mut(), mock_env(), mock_info("collection1", &[]), msg).unwrap();
+
+        // a native coin named like the token is not the token
+        let msg = ExecuteMsg::Buy { offering_id: 1 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(1000, "token")), msg)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::WrongDenom {}));
+
+        // only the token contract can pay in the token
+        let hook = Cw20ReceiveMsg {
+            sender: "buyer".to_string(),
+            amount: Uint128::new(1000),
+            msg: to_binary(&Cw20HookMsg::Buy { offering_id: 1 }).unwrap(),
+        };
+        let msg = ExecuteMsg::Receive(hook.clone());
+        let err = execute(deps.as_mut(), mock_env(), mock_info("fake", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::WrongDenom {}));
 
-         let sale_history = query_sale_history(deps.as_ref(),1,10, "collection1".to_string()).unwrap();
-        assert_eq!(sale_history,vec![SaleHistoryInfo{
-            from:"owner".to_string(),
-            to:"owner1".to_string(),
-            denom:"earth".to_string(),
-            amount:Uint128::new(1000),
-            time:mock_env().block.time.seconds(),
-            nft_address:"collection1".to_string(),
-            token_id:"1".to_string()
-        }]);
+        // removing the token stops new listings, but open ones can still be bought
+        let msg = ExecuteMsg::RemoveDenom { denom: "token".to_string() };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        assert_eq!(query_denoms(deps.as_ref()).unwrap().len(), 1);
+
+        let msg = ExecuteMsg::Receive(hook);
+        let res = execute(deps.as_mut(), mock_env(), mock_info("token", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: "token".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "owner".to_string(),
+                    amount: Uint128::new(980),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+
+        let state = query_state(deps.as_ref()).unwrap();
+        assert_eq!(state.tvl, coins(1000, "token"));
+        let sale_history = query_sale_history(deps.as_ref(), 1, 10, "collection1".to_string()).unwrap();
+        assert_eq!(sale_history[0].denom, "token".to_string());
+
+        let sell_msg = SellNft {
+            list_price: coin(1000, "token"),
+            image_url:"image".to_string(),
+            expires: None,
+            reserved_for: None,
+        };
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            token_id: "2".into(),
+            sender: "owner".into(),
+            msg: to_binary(&sell_msg).unwrap(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), mock_info("collection1", &[]), msg)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::WrongDenom {}));
     }
 
     #[test]
     fn withdraw_fees() {
         let mut deps = mock_dependencies_with_balance(&coins(1000, "earth"));
+        deps.querier.update_wasm(|_| no_royalty());
         setup(deps.as_mut());
 
+        // the balance is an escrowed offer, not fees
+        let msg = ExecuteMsg::MakeOffer {
+            collection: "collection1".to_string(),
+            token_id: "1".to_string(),
+            expires: Expiration::Never {},
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("bidder", &coins(1000, "earth")), msg).unwrap();
+
         let msg = ExecuteMsg::WithdrawFees {
-            amount: 1000u32.into(),
+            amount: 20u32.into(),
             denom: "earth".into(),
         };
         let info = mock_info("anyone", &[]);
@@ -559,24 +3884,82 @@ mod tests {
         }
 
         let info = mock_info("creator", &[]);
-        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientFunds {}));
+
+        list_nft(deps.as_mut(), "collection1", "2", "owner", 1000);
+        let buy = ExecuteMsg::Buy { offering_id: 1 };
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(1000, "earth")), buy).unwrap();
+        assert_eq!(query_accrued_fees(deps.as_ref()).unwrap(), coins(20, "earth"));
+
+        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg.clone()).unwrap();
         assert_eq!(1, res.messages.len());
         assert_eq!(
             res.messages[0],
             SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
                 to_address: "creator".into(),
-                amount: coins(1000, "earth")
+                amount: coins(20, "earth")
             }))
         );
+        assert_eq!(query_accrued_fees(deps.as_ref()).unwrap(), coins(0, "earth"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientFunds {}));
+    }
+
+    #[test]
+    fn distribute_fees() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let msg = ExecuteMsg::DistributeFees { denom: "earth".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg.clone())
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NoFeeRecipients {}));
+
+        let recipients = |second: u64| ExecuteMsg::SetFeeRecipients {
+            recipients: vec![
+                AdminInfo { address: "treasury".to_string(), portion: Decimal::percent(70) },
+                AdminInfo { address: "team".to_string(), portion: Decimal::percent(second) },
+            ],
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), recipients(20))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidPortions {}));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), recipients(30))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), recipients(30)).unwrap();
+
+        // 2% of 1050 is 21 accrued
+        list_nft(deps.as_mut(), "collection1", "1", "owner", 1050);
+        let buy = ExecuteMsg::Buy { offering_id: 1 };
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(1050, "earth")), buy).unwrap();
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(BankMsg::Send {
+                    to_address: "treasury".into(),
+                    amount: coins(14, "earth"),
+                }),
+                SubMsg::new(BankMsg::Send {
+                    to_address: "team".into(),
+                    amount: coins(6, "earth"),
+                }),
+            ]
+        );
+        // the rounding dust waits for the next distribution
+        assert_eq!(query_accrued_fees(deps.as_ref()).unwrap(), coins(1, "earth"));
     }
 
     #[test]
     fn change_fee() {
-        let mut deps = mock_dependencies();
+        let mut deps = mock_deps();
         setup(deps.as_mut());
 
         let msg = ExecuteMsg::ChangeFee {
-            fee: Decimal::percent(3),
+            fee: Decimal::percent(1),
         };
         let info = mock_info("anyone", &[]);
         let res = execute(deps.as_mut(), mock_env(), info, msg.clone());
@@ -592,6 +3975,84 @@ mod tests {
         let msg = QueryMsg::GetFee {};
         let res = query(deps.as_ref(), mock_env(), msg).unwrap();
         let value: FeeResponse = from_binary(&res).unwrap();
-        assert_eq!(Decimal::percent(3), value.fee);
+        assert_eq!(Decimal::percent(1), value.fee);
+
+        // raising the fee needs notice
+        let msg = ExecuteMsg::ChangeFee {
+            fee: Decimal::percent(3),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::FeeTimelocked {}));
+    }
+
+    #[test]
+    fn timelocked_fee_increase() {
+        let mut deps = mock_deps();
+        setup(deps.as_mut());
+
+        let msg = ExecuteMsg::ProposeFee { fee: Decimal::percent(11) };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidFee {}));
+
+        let msg = ExecuteMsg::ProposeFee { fee: Decimal::percent(5) };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        let fee = query_fee(deps.as_ref()).unwrap();
+        assert_eq!(fee.fee, Decimal::percent(2));
+        assert_eq!(fee.pending_fee, Some(PendingFee {
+            fee: Decimal::percent(5),
+            apply_after: mock_env().block.time.seconds() + 86400,
+        }));
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), ExecuteMsg::ApplyFee {})
+            .unwrap_err();
+        assert!(matches!(err, ContractError::FeeTimelocked {}));
+
+        let mut later = mock_env();
+        later.block.time = later.block.time.plus_seconds(86400);
+        execute(deps.as_mut(), later.clone(), mock_info("creator", &[]), ExecuteMsg::ApplyFee {}).unwrap();
+        let fee = query_fee(deps.as_ref()).unwrap();
+        assert_eq!(fee.fee, Decimal::percent(5));
+        assert_eq!(fee.pending_fee, None);
+
+        let err = execute(deps.as_mut(), later, mock_info("creator", &[]), ExecuteMsg::ApplyFee {})
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NoPendingFee {}));
+
+        // the limit can not drop below the current fee, nor reach one
+        let msg = ExecuteMsg::SetFeeLimits { max_fee: Decimal::percent(4), fee_timelock: 0 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidFee {}));
+        let msg = ExecuteMsg::SetFeeLimits { max_fee: Decimal::one(), fee_timelock: 0 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidFee {}));
+
+        // a timelock that would overflow the block time is refused up front
+        let msg = ExecuteMsg::SetFeeLimits { max_fee: Decimal::percent(10), fee_timelock: u64::MAX };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidFeeTimelock { max: MAX_FEE_TIMELOCK }));
+        // and one already stored fails the proposal instead of panicking
+        STATE
+            .update(deps.as_mut().storage, |mut state| -> StdResult<_> {
+                state.fee_timelock = u64::MAX;
+                Ok(state)
+            })
+            .unwrap();
+        let msg = ExecuteMsg::ProposeFee { fee: Decimal::percent(3) };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidFeeTimelock { .. }));
+    }
+
+    #[test]
+    fn fee_above_maximum_is_rejected_at_instantiate() {
+        let mut deps = mock_deps();
+        let msg = InstantiateMsg {
+            fee: Decimal::one(),
+            max_fee: Decimal::one(),
+            fee_timelock: 0,
+            denom: "earth".to_string(),
+            factory: None,
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidFee {}));
     }
 }
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,6 +8,18 @@ pub enum ContractError {
 
     #[error("Unauthorized")]
     Unauthorized {},
+
+    #[error("Cannot migrate from different contract type: {previous_contract}")]
+    CannotMigrate { previous_contract: String },
+
+    #[error("Cannot migrate from newer version {previous_version}")]
+    CannotMigrateVersion { previous_version: String },
+
+    #[error("Listings and sales of the previous version are still being migrated")]
+    MigrationPending {},
+
+    #[error("No migration is in progress")]
+    NoMigrationPending {},
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
     #[error("Insufficient funds")]
@@ -27,4 +39,67 @@ pub enum ContractError {
 
     #[error("Wrong Page Number")]
     WrongPageNumber {},
+
+    #[error("Invalid auction parameters")]
+    InvalidAuction {},
+
+    #[error("Auction has not started yet")]
+    AuctionNotStarted {},
+
+    #[error("Auction has ended")]
+    AuctionEnded {},
+
+    #[error("Auction has not ended yet")]
+    AuctionNotEnded {},
+
+    #[error("Bid too low, minimum is {min}")]
+    BidTooLow { min: Uint128 },
+
+    #[error("Cannot make an offer that is already expired")]
+    InvalidExpiration {},
+
+    #[error("Offer has expired")]
+    OfferExpired {},
+
+    #[error("NFT does not match the offer")]
+    OfferMismatch {},
+
+    #[error("Marketplace is not approved for this token by its owner")]
+    NotApproved {},
+
+    #[error("Listed token moved or its approval was revoked")]
+    StaleListing {},
+
+    #[error("Listing has expired")]
+    ListingExpired {},
+
+    #[error("A trade needs a counterparty other than the proposer and distinct NFTs on both sides")]
+    InvalidTrade {},
+
+    #[error("NFT is not requested by the trade or was already sent")]
+    NftNotRequested {},
+
+    #[error("Trade has expired")]
+    TradeExpired {},
+
+    #[error("Fee must be below one and at most the maximum fee")]
+    InvalidFee {},
+
+    #[error("Fee timelock must be at most {max} seconds")]
+    InvalidFeeTimelock { max: u64 },
+
+    #[error("Fee increases must be proposed and wait for the fee timelock")]
+    FeeTimelocked {},
+
+    #[error("No fee change is pending")]
+    NoPendingFee {},
+
+    #[error("Marketplace is paused")]
+    Paused {},
+
+    #[error("Fee portions must sum to one")]
+    InvalidPortions {},
+
+    #[error("No fee recipients configured")]
+    NoFeeRecipients {},
 }
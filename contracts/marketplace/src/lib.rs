@@ -1,5 +1,8 @@
 pub mod contract;
+pub mod cw20;
 pub mod cw721;
 pub mod error;
+pub mod migrations;
 pub mod msg;
 pub mod state;
+pub mod trade;
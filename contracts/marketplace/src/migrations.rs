@@ -0,0 +1,354 @@
+//! Storage layouts of earlier releases and their conversion to the current one.
+
+use cosmwasm_std::{coin, Coin, Order, Storage, Uint128};
+use cw_storage_plus::{Bound, Item};
+use cw_utils::Expiration;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::contract::{check_fee, record_sale_stats};
+use crate::error::ContractError;
+use crate::state::{
+    next_offering_id, next_sale_id, offerings, sales, CollectionInfo, Custody, DenomInfo,
+    DenomKind, Offering, SaleHistoryInfo, State, COLLECTIONINFO, DENOMS, SELLER_VOLUME, STATE,
+};
+
+/// 0.1: a single native denom, listings keyed by `(collection, id)` with ids
+/// reused on withdrawal, and sale history keyed by `(collection, sale number)`.
+pub mod v0_1 {
+    use cosmwasm_std::{Addr, Coin, Decimal, Uint128};
+    use cw_storage_plus::{Item, Map};
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct State {
+        pub fee: Decimal,
+        pub owner: Addr,
+        pub tvl: Uint128,
+        pub denom: String,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct Offering {
+        pub token_id: String,
+        pub contract: Addr,
+        pub seller: Addr,
+        pub list_price: Coin,
+        pub image_url: String,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct SaleHistoryInfo {
+        pub from: String,
+        pub to: String,
+        pub denom: String,
+        pub amount: Uint128,
+        pub time: u64,
+        pub nft_address: String,
+        pub token_id: String,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct CollectionInfo {
+        pub sale_id: u64,
+        pub tvl: Uint128,
+        pub num_offerings: u64,
+    }
+
+    pub const STATE: Item<State> = Item::new("state");
+    pub const OFFERINGS: Map<(&str, &str), Offering> = Map::new("offerings");
+    pub const SALEHISTORY: Map<(&str, &str), SaleHistoryInfo> = Map::new("sale history");
+    pub const COLLECTIONINFO: Map<&str, CollectionInfo> = Map::new("collection_info");
+}
+
+/// Listings and sales moved per `MigrateBatch` unless the caller asks otherwise.
+pub const DEFAULT_BATCH: u32 = 100;
+pub const MAX_BATCH: u32 = 500;
+
+/// Progress of a 0.1 migration. Listings are moved first, then the sales of one
+/// collection after the other in sale order. Removed once everything is moved.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrationProgress {
+    /// collection whose sales are being moved, None once all are
+    pub collection: Option<String>,
+    /// 0.1 sale number of the next sale of `collection`
+    pub next_sale: u64,
+}
+
+pub const MIGRATION: Item<MigrationProgress> = Item::new("migration_v0_1");
+
+/// Moves the 0.1 state, denom and collections to the current layout and pauses
+/// the contract. Listings and sales can be too many for one transaction, so they
+/// are moved by `migrate_batch_v0_1` and the contract stays paused until then.
+pub fn migrate_from_v0_1(storage: &mut dyn Storage) -> Result<(), ContractError> {
+    let legacy = v0_1::STATE.load(storage)?;
+    // the old fee had no bound, so it is the maximum until the owner sets one
+    check_fee(legacy.fee, legacy.fee)?;
+    let state = State {
+        fee: legacy.fee,
+        max_fee: legacy.fee,
+        fee_timelock: 0,
+        pending_fee: None,
+        owner: legacy.owner.clone(),
+        tvl: volume(legacy.tvl, &legacy.denom),
+        factory: None,
+        pending_owner: None,
+        fee_manager: legacy.owner.clone(),
+        pauser: legacy.owner,
+        paused: true,
+    };
+    STATE.save(storage, &state)?;
+    let denom_info = DenomInfo {
+        kind: DenomKind::Native,
+        accepted: true,
+    };
+    DENOMS.save(storage, &legacy.denom, &denom_info)?;
+
+    // one entry per listed collection, a small set next to listings and sales
+    let collections = v0_1::COLLECTIONINFO
+        .range(storage, None, None, Order::Ascending)
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = collections.first().map(|(address, _)| address.clone());
+    for (address, info) in collections {
+        let info = CollectionInfo {
+            sale_id: info.sale_id,
+            tvl: volume(info.tvl, &legacy.denom),
+            num_offerings: info.num_offerings,
+        };
+        COLLECTIONINFO.save(storage, &address, &info)?;
+    }
+
+    let progress = MigrationProgress {
+        collection: first,
+        next_sale: 1,
+    };
+    MIGRATION.save(storage, &progress)?;
+    Ok(())
+}
+
+/// Moves up to `limit` 0.1 listings and sales, giving them new global ids.
+/// Returns true once nothing is left.
+pub fn migrate_batch_v0_1(storage: &mut dyn Storage, limit: u32) -> Result<bool, ContractError> {
+    let mut progress = MIGRATION.may_load(storage)?.ok_or(ContractError::NoMigrationPending {})?;
+    let mut budget = limit as usize;
+
+    // moved listings are removed, so the next ones are always at the start
+    let listings = v0_1::OFFERINGS
+        .range(storage, None, None, Order::Ascending)
+        .take(budget)
+        .collect::<Result<Vec<_>, _>>()?;
+    budget -= listings.len();
+    for ((collection, id), off) in listings {
+        v0_1::OFFERINGS.remove(storage, (&collection, &id));
+        let off = Offering {
+            token_id: off.token_id,
+            contract: off.contract,
+            seller: off.seller,
+            list_price: off.list_price,
+            image_url: off.image_url,
+            custody: Custody::Escrow,
+            expires: Expiration::Never {},
+            reserved_for: None,
+        };
+        let id = next_offering_id(storage)?;
+        offerings().save(storage, id, &off)?;
+    }
+
+    // 0.1 sales are numbered from 1 to `sale_id` in each collection
+    while budget > 0 {
+        let collection = match progress.collection.clone() {
+            Some(collection) => collection,
+            None => break,
+        };
+        if progress.next_sale > COLLECTIONINFO.load(storage, &collection)?.sale_id {
+            progress.collection = COLLECTIONINFO
+                .keys(storage, Some(Bound::exclusive(collection.as_str())), None, Order::Ascending)
+                .next()
+                .transpose()?;
+            progress.next_sale = 1;
+            continue;
+        }
+        let number = progress.next_sale.to_string();
+        if let Some(sale) = v0_1::SALEHISTORY.may_load(storage, (&collection, &number))? {
+            v0_1::SALEHISTORY.remove(storage, (&collection, &number));
+            migrate_sale(storage, sale)?;
+        }
+        progress.next_sale += 1;
+        budget -= 1;
+    }
+
+    let listings_left = v0_1::OFFERINGS
+        .keys_raw(storage, None, None, Order::Ascending)
+        .next()
+        .is_some();
+    let done = progress.collection.is_none() && !listings_left;
+    if done {
+        MIGRATION.remove(storage);
+    } else {
+        MIGRATION.save(storage, &progress)?;
+    }
+    Ok(done)
+}
+
+fn migrate_sale(storage: &mut dyn Storage, sale: v0_1::SaleHistoryInfo) -> Result<(), ContractError> {
+    // fees and royalties of 0.1 sales were not recorded
+    let sale = SaleHistoryInfo {
+        from: sale.from,
+        to: sale.to,
+        denom: sale.denom,
+        amount: sale.amount,
+        royalty_amount: Uint128::zero(),
+        fee_amount: Uint128::zero(),
+        time: sale.time,
+        nft_address: sale.nft_address,
+        token_id: sale.token_id,
+    };
+    record_sale_stats(storage, &sale, &coin(sale.amount.u128(), sale.denom.clone()))?;
+    SELLER_VOLUME.update(storage, (&sale.from, &sale.denom), |amount| -> Result<_, ContractError> {
+        Ok(amount.unwrap_or_default() + sale.amount)
+    })?;
+    let id = next_sale_id(storage)?;
+    sales().save(storage, id, &sale)?;
+    Ok(())
+}
+
+fn volume(amount: Uint128, denom: &str) -> Vec<Coin> {
+    match amount.is_zero() {
+        true => vec![],
+        false => vec![coin(amount.u128(), denom)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::{execute, migrate, query};
+    use crate::msg::{ExecuteMsg, MigrateMsg, QueryMsg};
+    use crate::state::{CollectionInfo, OfferingResult, SaleResult};
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{attr, coins, from_binary, Addr, Decimal};
+    use cw2::{get_contract_version, set_contract_version};
+
+    fn legacy_offering(token_id: &str, price: u128) -> v0_1::Offering {
+        v0_1::Offering {
+            token_id: token_id.to_string(),
+            contract: Addr::unchecked("collection1"),
+            seller: Addr::unchecked("seller"),
+            list_price: coin(price, "earth"),
+            image_url: "image".to_string(),
+        }
+    }
+
+    fn legacy_sale(token_id: &str, to: &str, time: u64) -> v0_1::SaleHistoryInfo {
+        v0_1::SaleHistoryInfo {
+            from: "seller".to_string(),
+            to: to.to_string(),
+            denom: "earth".to_string(),
+            amount: Uint128::new(500),
+            time,
+            nft_address: "collection1".to_string(),
+            token_id: token_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn migrate_v0_1_state() {
+        let mut deps = mock_dependencies();
+        let storage = deps.as_mut().storage;
+        set_contract_version(storage, "human_market_place", "0.1.0").unwrap();
+        let state = v0_1::State {
+            fee: Decimal::percent(2),
+            owner: Addr::unchecked("creator"),
+            tvl: Uint128::new(1000),
+            denom: "earth".to_string(),
+        };
+        v0_1::STATE.save(storage, &state).unwrap();
+        let info = v0_1::CollectionInfo {
+            sale_id: 2,
+            tvl: Uint128::new(1000),
+            num_offerings: 2,
+        };
+        v0_1::COLLECTIONINFO.save(storage, "collection1", &info).unwrap();
+        // listing ids were strings, so "10" sorts before "2"
+        v0_1::OFFERINGS.save(storage, ("collection1", "2"), &legacy_offering("a", 100)).unwrap();
+        v0_1::OFFERINGS.save(storage, ("collection1", "10"), &legacy_offering("b", 200)).unwrap();
+        v0_1::SALEHISTORY.save(storage, ("collection1", "1"), &legacy_sale("c", "alice", 10)).unwrap();
+        v0_1::SALEHISTORY.save(storage, ("collection1", "2"), &legacy_sale("d", "bob", 20)).unwrap();
+        let info = v0_1::CollectionInfo {
+            sale_id: 1,
+            tvl: Uint128::zero(),
+            num_offerings: 0,
+        };
+        v0_1::COLLECTIONINFO.save(storage, "collection2", &info).unwrap();
+        v0_1::SALEHISTORY.save(storage, ("collection2", "1"), &legacy_sale("e", "carol", 5)).unwrap();
+
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        assert_eq!(get_contract_version(&deps.storage).unwrap().version, env!("CARGO_PKG_VERSION"));
+
+        // listings and sales move in batches, and the contract stays paused until then
+        let creator = mock_info("creator", &[]);
+        let batch = ExecuteMsg::MigrateBatch { limit: Some(2) };
+        for done in ["false", "false", "true"] {
+            let err = execute(deps.as_mut(), mock_env(), creator.clone(), ExecuteMsg::Unpause {})
+                .unwrap_err();
+            assert!(matches!(err, ContractError::MigrationPending {}));
+            let res = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), batch.clone())
+                .unwrap();
+            assert_eq!(res.attributes[1], attr("done", done));
+        }
+        let err = execute(deps.as_mut(), mock_env(), creator.clone(), batch).unwrap_err();
+        assert!(matches!(err, ContractError::NoMigrationPending {}));
+        assert!(STATE.load(&deps.storage).unwrap().paused);
+        execute(deps.as_mut(), mock_env(), creator, ExecuteMsg::Unpause {}).unwrap();
+
+        let state = STATE.load(&deps.storage).unwrap();
+        assert_eq!(state.tvl, coins(1000, "earth"));
+        assert_eq!(state.max_fee, Decimal::percent(2));
+        assert_eq!(state.fee_manager, "creator");
+        assert!(DENOMS.load(&deps.storage, "earth").unwrap().accepted);
+        assert_eq!(COLLECTIONINFO.load(&deps.storage, "collection1").unwrap(), CollectionInfo {
+            sale_id: 2,
+            tvl: coins(1000, "earth"),
+            num_offerings: 2,
+        });
+
+        let msg = QueryMsg::GetOffers {
+            address: "collection1".to_string(),
+            include_expired: None,
+            start_after: None,
+            limit: None,
+        };
+        let listings: Vec<OfferingResult> =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        let listed: Vec<_> = listings.iter().map(|off| (off.id, off.token_id.as_str())).collect();
+        assert_eq!(listed, vec![(1, "b"), (2, "a")]);
+
+        let msg = QueryMsg::GetSalesByAddress {
+            address: "seller".to_string(),
+            start_after: None,
+            limit: None,
+        };
+        let sales: Vec<SaleResult> =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        let sold: Vec<_> = sales.iter().map(|sale| (sale.id, sale.to.as_str())).collect();
+        assert_eq!(sold, vec![(3, "carol"), (2, "bob"), (1, "alice")]);
+
+        let legacy_listings = v0_1::OFFERINGS.range(&deps.storage, None, None, Order::Ascending);
+        assert_eq!(legacy_listings.count(), 0);
+        let legacy_sales = v0_1::SALEHISTORY.range(&deps.storage, None, None, Order::Ascending);
+        assert_eq!(legacy_sales.count(), 0);
+    }
+
+    #[test]
+    fn refuse_downgrade_and_other_contracts() {
+        let mut deps = mock_dependencies();
+        set_contract_version(deps.as_mut().storage, "human_market_place", "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert!(matches!(err, ContractError::CannotMigrateVersion { .. }));
+
+        set_contract_version(deps.as_mut().storage, "user_create", "0.1.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert!(matches!(err, ContractError::CannotMigrate { .. }));
+    }
+}
@@ -1,42 +1,289 @@
+use crate::cw20::Cw20ReceiveMsg;
 use crate::cw721::Cw721ReceiveMsg;
+use crate::state::{AdminInfo, DenomKind, FeeTier, PendingFee};
 use cosmwasm_std::{Addr, Coin, Decimal, Uint128};
+use cw_utils::Expiration;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
     pub fee: Decimal,
-    pub denom : String
+    /// Must be below one, and at least `fee`.
+    pub max_fee: Decimal,
+    /// Notice in seconds sellers get before a fee increase, at most 30 days.
+    pub fee_timelock: u64,
+    /// First accepted native denom. More can be added with `AddDenom`.
+    pub denom : String,
+    /// `user_create` factory. Without it, any collection not denied can be listed.
+    pub factory: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
     Buy {
-        offering_id: String,
-        address:String
+        offering_id: u64,
+    },
+    /// Buys `(collection, offering_id)` listings with one payment covering all of
+    /// them. Unspent funds are refunded. With `best_effort`, listings that can not
+    /// be bought (sold, withdrawn, too expensive) are skipped instead of failing.
+    BuyBatch {
+        items: Vec<(String, u64)>,
+        best_effort: bool,
     },
     WithdrawNft {
-        offering_id: String,
-        address:String
+        offering_id: u64,
+    },
+    /// only seller of every listing.
+    WithdrawBatch {
+        offering_ids: Vec<u64>,
+    },
+    /// Lists tokens of `collection` owned by the sender, who must have granted the
+    /// marketplace `ApproveAll`. The marketplace pulls them with `TransferNft`.
+    ListBatch {
+        collection: String,
+        items: Vec<ListItem>,
+    },
+    /// Lists a token without escrow. The sender must own it and `Approve` the
+    /// marketplace for it; the marketplace transfers it from the seller on `Buy`.
+    ListWithApproval {
+        collection: String,
+        item: ListItem,
+    },
+    /// anyone. Removes the given approval listings that went stale because the
+    /// token moved or the approval was revoked or expired.
+    PruneListings {
+        offering_ids: Vec<u64>,
+    },
+    /// anyone. Removes up to `limit` expired listings of a collection and returns
+    /// escrowed NFTs to their sellers. Reads at most 100 listings after
+    /// `start_after` and answers the last one read as `last_offering_id`, to
+    /// continue from in the next call.
+    PruneExpired {
+        collection: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// only seller. Changes the price of a listing without withdrawing the NFT.
+    UpdatePrice {
+        offering_id: u64,
+        list_price: Coin,
     },
     ReceiveNft(Cw721ReceiveMsg),
-    /// only admin.
+    /// Pays with an accepted CW20 token. The payload is a `Cw20HookMsg`.
+    Receive(Cw20ReceiveMsg),
+    /// only admin. None lets any collection that is not denied be listed.
+    SetFactory {
+        factory: Option<String>,
+    },
+    /// only admin. Allows a collection the factory does not know.
+    AddCollection {
+        address: String,
+    },
+    /// only admin. Rejects listings and offers, even for factory collections.
+    DenyCollection {
+        address: String,
+    },
+    /// only admin. Whether sales of the collection pay the royalty its
+    /// `RoyaltyInfo` query returns. Other collections are sold without royalty.
+    SetRoyaltySupport {
+        address: String,
+        supported: bool,
+    },
+    /// only admin. Accepts a native denom or, for `DenomKind::Cw20`, the token
+    /// with that contract address.
+    AddDenom {
+        denom: String,
+        kind: DenomKind,
+    },
+    /// only admin. Open listings, auctions and offers in the denom are unaffected.
+    RemoveDenom {
+        denom: String,
+    },
+    /// only fee manager. Limited to the accrued fees of `denom`.
     WithdrawFees {
         amount: Uint128,
         denom: String,
     },
-    /// only admin.
+    /// only fee manager. Lowers the fee right away; increases go through `ProposeFee`.
     ChangeFee {
         fee: Decimal,
-    }
+    },
+    /// only fee manager. Replaces any pending fee change.
+    ProposeFee {
+        fee: Decimal,
+    },
+    /// only fee manager, once the fee timelock has passed.
+    ApplyFee {},
+    /// only fee manager. Fee of a partner collection, `None` to remove it. Like the
+    /// fee tiers it only applies where it is lower than the marketplace fee.
+    SetCollectionFee {
+        collection: String,
+        fee: Option<Decimal>,
+    },
+    /// only fee manager. Replaces the volume-based seller fee tiers.
+    SetFeeTiers {
+        tiers: Vec<FeeTier>,
+    },
+    /// only admin. `max_fee` must be below one and not below the current fee,
+    /// `fee_timelock` at most 30 days.
+    SetFeeLimits {
+        max_fee: Decimal,
+        fee_timelock: u64,
+    },
+    /// only fee manager. Portions must sum to one.
+    SetFeeRecipients {
+        recipients: Vec<AdminInfo>,
+    },
+    /// only admin. `new_owner` takes over once it sends `AcceptOwnership`.
+    TransferOwnership {
+        new_owner: String,
+    },
+    /// only the pending owner.
+    AcceptOwnership {},
+    /// only admin.
+    SetRoles {
+        fee_manager: String,
+        pauser: String,
+    },
+    /// only pauser or admin. Halts listing, buying, bidding, offers and trades.
+    /// Withdrawals, cancellations and settling ended auctions keep working.
+    Pause {},
+    /// only pauser or admin.
+    Unpause {},
+    /// anyone. Pays the accrued fees of `denom` out to the fee recipients.
+    DistributeFees {
+        denom: String,
+    },
+    /// Escrows the sent funds as the new highest bid and refunds the previous one.
+    PlaceBid {
+        auction_id: u64,
+        address: String,
+    },
+    /// anyone, once the auction has ended.
+    SettleAuction {
+        auction_id: u64,
+        address: String,
+    },
+    /// Escrows the sent funds as an offer on one token. The owner accepts it by
+    /// sending the NFT with `ReceiveNftMsg::AcceptOffer`.
+    MakeOffer {
+        collection: String,
+        token_id: String,
+        expires: Expiration,
+    },
+    /// Like `MakeOffer`, but any token of the collection can fill it.
+    MakeCollectionOffer {
+        collection: String,
+        expires: Expiration,
+    },
+    /// only bidder. Refunds the escrowed funds, also after expiry.
+    CancelOffer {
+        offer_id: u64,
+    },
+    /// Escrows `offered_nfts`, which the sender must have approved the marketplace
+    /// for, and the sent funds, in exchange for `requested_nfts` of `counterparty`.
+    /// The counterparty accepts by sending each requested NFT with
+    /// `ReceiveNftMsg::AcceptTrade`.
+    ProposeTrade {
+        counterparty: String,
+        offered_nfts: Vec<NftItem>,
+        requested_nfts: Vec<NftItem>,
+        expires: Expiration,
+    },
+    /// Returns the escrow to the proposer. Proposer or counterparty at any time,
+    /// anyone once the trade has expired.
+    CancelTrade {
+        trade_id: u64,
+    },
+    /// anyone, after migrating from 0.1. Moves up to `limit` of the old listings
+    /// and sales. The contract stays paused until all of them are moved.
+    MigrateBatch {
+        limit: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct NftItem {
+    pub collection: String,
+    pub token_id: String,
+}
+
+/// CW20 counterparts of the native payment messages. The sent tokens are the funds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw20HookMsg {
+    Buy {
+        offering_id: u64,
+    },
+    BuyBatch {
+        items: Vec<(String, u64)>,
+        best_effort: bool,
+    },
+    PlaceBid {
+        auction_id: u64,
+        address: String,
+    },
+    MakeOffer {
+        collection: String,
+        token_id: String,
+        expires: Expiration,
+    },
+    MakeCollectionOffer {
+        collection: String,
+        expires: Expiration,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct SellNft {
     pub list_price: Coin,
-    pub image_url:String
+    pub image_url:String,
+    /// Never expires if unset.
+    pub expires: Option<Expiration>,
+    /// Makes a private sale that only this address can buy.
+    pub reserved_for: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ListItem {
+    pub token_id: String,
+    pub list_price: Coin,
+    pub image_url: String,
+    /// Never expires if unset.
+    pub expires: Option<Expiration>,
+    /// Makes a private sale that only this address can buy.
+    pub reserved_for: Option<String>,
+}
+
+/// `ReceiveNft` payloads other than a fixed-price `SellNft`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveNftMsg {
+    StartAuction(StartAuction),
+    /// Sells the NFT to the bidder of a `MakeOffer` or `MakeCollectionOffer`.
+    AcceptOffer { offer_id: u64 },
+    /// only counterparty. Escrows one of the requested NFTs of a trade. The swap
+    /// happens when the last one arrives.
+    AcceptTrade { trade_id: u64 },
+}
+
+/// Lists the NFT in a timed English auction. Times are unix seconds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct StartAuction {
+    pub reserve_price: Coin,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub min_bid_increment: Uint128,
+    pub image_url: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -44,25 +291,175 @@ pub struct SellNft {
 pub enum QueryMsg {
     GetStateInfo{},
     GetCollectionInfo{address:String},
+    /// Floor price, recent volume and sale aggregates of a collection.
+    /// Return type: CollectionStatsResponse.
+    CollectionStats { address: String },
     GetFee {},
-    /// With Enumerable extension.
-    /// Requires pagination. Lists all offers controlled by the contract.
-    /// Return type: OffersResponse.
+    /// Return type: Option<Decimal>.
+    GetCollectionFee { collection: String },
+    /// Return type: Vec<FeeTier>.
+    GetFeeTiers {},
+    /// Cumulative sale volume of `seller`, one coin per denom.
+    /// Return type: Vec<Coin>.
+    GetSellerVolume { seller: String },
+    /// Whether the collection can be listed. Return type: bool.
+    CheckCollection { address: String },
+    /// Whether sales of the collection pay royalty. Return type: bool.
+    CheckRoyaltySupport { address: String },
+    /// Return type: Vec<DenomResponse>.
+    GetDenoms {},
+    /// Fees not yet withdrawn or distributed, one coin per denom.
+    /// Return type: Vec<Coin>.
+    GetAccruedFees {},
+    /// Return type: Vec<AdminInfo>.
+    GetFeeRecipients {},
+    /// Lists the fixed-price listings of a collection, ordered by offering id.
+    /// Expired listings are left out unless `include_expired` is set.
+    /// Return type: Vec<OfferingResult>.
     GetOffers {
-        page_num: u32,
-        page_count: u32,
-        address:String
+        address: String,
+        include_expired: Option<bool>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Return type: Vec<OfferingResult>.
+    GetOffersBySeller {
+        seller: String,
+        include_expired: Option<bool>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Listings of a collection in `denom` from the cheapest up. `start_after`
+    /// is the `(list price amount, offering id)` of the last result.
+    /// Return type: Vec<OfferingResult>.
+    GetOffersByPrice {
+        address: String,
+        denom: String,
+        include_expired: Option<bool>,
+        start_after: Option<(Uint128, u64)>,
+        limit: Option<u32>,
+    },
+    /// Private listings only `buyer` can buy, ordered by offering id.
+    /// Return type: Vec<OfferingResult>.
+    GetOffersReservedFor {
+        buyer: String,
+        include_expired: Option<bool>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Return type: OfferingResult.
+    GetOffering {
+        offering_id: u64,
+    },
+    /// False for approval listings that can no longer be bought.
+    /// Return type: bool.
+    IsListingValid {
+        offering_id: u64,
     },
     GetSaleHistory{
         page_num: u32,
         count: u32,
         address:String
-    }
+    },
+    /// Sales where `address` was the seller or the buyer, newest first.
+    /// Return type: Vec<SaleResult>.
+    GetSalesByAddress {
+        address: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Provenance of a token, newest sale first.
+    /// Return type: Vec<SaleResult>.
+    GetTokenSaleHistory {
+        collection: String,
+        token_id: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Lists unsettled auctions of a collection, ordered by auction id.
+    /// Return type: Vec<AuctionResult>.
+    GetAuctions {
+        address: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Return type: AuctionResult, including the current highest bid.
+    GetAuction {
+        address: String,
+        auction_id: u64,
+    },
+    /// Token and collection-wide buy offers on a collection, ordered by offer id.
+    /// Return type: Vec<BuyOfferResult>.
+    GetBuyOffersByCollection {
+        collection: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Buy offers made on one token. Collection-wide offers are not included.
+    /// Return type: Vec<BuyOfferResult>.
+    GetBuyOffersByToken {
+        collection: String,
+        token_id: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Return type: Vec<BuyOfferResult>.
+    GetBuyOffersByBidder {
+        bidder: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Return type: TradeResult.
+    GetTrade { trade_id: u64 },
+    /// Trades proposed by `proposer`, ordered by trade id.
+    /// Return type: Vec<TradeResult>.
+    GetTradesByProposer {
+        proposer: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Trades waiting for `counterparty` to accept, ordered by trade id.
+    /// Return type: Vec<TradeResult>.
+    GetTradesByCounterparty {
+        counterparty: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FactoryQueryMsg {
+    CheckCollection { address: String },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct FeeResponse {
     pub fee: Decimal,
+    pub max_fee: Decimal,
+    pub fee_timelock: u64,
+    pub pending_fee: Option<PendingFee>,
+}
+
+/// Amounts are given per denom, as listings and sales can be in any accepted denom.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CollectionStatsResponse {
+    /// Cheapest public, unexpired listing in each accepted denom, among the 100
+    /// cheapest listings of the collection in that denom.
+    pub floor_price: Vec<Coin>,
+    pub volume_24h: Vec<Coin>,
+    pub volume_7d: Vec<Coin>,
+    pub total_volume: Vec<Coin>,
+    pub average_price: Vec<Coin>,
+    pub last_sale_price: Option<Coin>,
+    pub num_sales: u64,
+    pub unique_traders: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DenomResponse {
+    pub denom: String,
+    pub kind: DenomKind,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -2,15 +2,69 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::error::ContractError;
-use cosmwasm_std::{Addr, Api, Coin, Decimal, StdResult, Storage, Uint128};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Addr, Api, Coin, Decimal, StdError, StdResult, Storage, Uint128};
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex};
+use cw_utils::Expiration;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct State {
     pub fee: Decimal,
+    /// Upper bound of `fee`, always below one.
+    pub max_fee: Decimal,
+    /// Seconds a proposed fee increase waits before it can be applied.
+    pub fee_timelock: u64,
+    pub pending_fee: Option<PendingFee>,
     pub owner: Addr,
-    pub tvl:Uint128,
-    pub denom:String
+    /// Sale volume, one entry per denom.
+    pub tvl: Vec<Coin>,
+    /// `user_create` factory asked whether a collection may be listed.
+    pub factory: Option<Addr>,
+    /// Set by `TransferOwnership` until the new owner accepts.
+    pub pending_owner: Option<Addr>,
+    /// Changes the fee, fee recipients and withdraws accrued fees.
+    pub fee_manager: Addr,
+    /// Can pause and unpause trading, as can the owner.
+    pub pauser: Addr,
+    /// While paused nothing can be listed or bought; sellers can still withdraw.
+    pub paused: bool,
+}
+
+/// Fee change proposed with `ProposeFee`, applicable from `apply_after` on.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingFee {
+    pub fee: Decimal,
+    /// Block time in seconds.
+    pub apply_after: u64,
+}
+
+/// Fee for sellers whose past sales in `min_volume`'s denom reach its amount.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeTier {
+    pub min_volume: Coin,
+    pub fee: Decimal,
+}
+
+/// How a payment denom is transferred. CW20 tokens use their contract address
+/// as the denom of prices and sale history.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DenomKind {
+    Native,
+    Cw20,
+}
+
+/// Fee recipient and its share of distributed fees. The shares sum to one.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AdminInfo {
+    pub address: String,
+    pub portion: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DenomInfo {
+    pub kind: DenomKind,
+    /// Removed denoms are kept unaccepted so open listings can still be paid out.
+    pub accepted: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -19,46 +73,333 @@ pub struct Offering {
     pub contract: Addr,
     pub seller: Addr,
     pub list_price: Coin,
-    pub image_url:String
+    pub image_url:String,
+    #[serde(default)]
+    pub custody: Custody,
+    /// Expired listings can not be bought and are returned by `PruneExpired`.
+    #[serde(default)]
+    pub expires: Expiration,
+    /// Only this address can buy a private listing.
+    #[serde(default)]
+    pub reserved_for: Option<Addr>,
+}
+
+/// Where a listed NFT is held until it is sold.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Custody {
+    /// Sent to the marketplace.
+    #[default]
+    Escrow,
+    /// Kept by the seller, who approved the marketplace to transfer it.
+    Approval,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 pub struct OfferingResult {
-    pub id:String,
+    pub id: u64,
     pub token_id: String,
     pub contract: Addr,
     pub seller: Addr,
     pub list_price: Coin,
-    pub image_url:String
+    pub image_url:String,
+    pub custody: Custody,
+    pub expires: Expiration,
+    pub reserved_for: Option<Addr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Auction {
+    pub token_id: String,
+    pub contract: Addr,
+    pub seller: Addr,
+    pub reserve_price: Coin,
+    pub min_bid_increment: Uint128,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub image_url: String,
+    pub highest_bid: Option<Bid>,
 }
 
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Bid {
+    pub bidder: Addr,
+    pub amount: Uint128,
+    pub time: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct AuctionResult {
+    pub id: u64,
+    pub token_id: String,
+    pub contract: Addr,
+    pub seller: Addr,
+    pub reserve_price: Coin,
+    pub min_bid_increment: Uint128,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub image_url: String,
+    pub highest_bid: Option<Bid>,
+}
+
+/// Escrowed bid from a buyer, either on one token or on any token of a collection.
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct BuyOffer {
+    pub bidder: Addr,
+    pub collection: Addr,
+    /// None for a collection-wide offer
+    pub token_id: Option<String>,
+    pub price: Coin,
+    pub expires: Expiration,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct BuyOfferResult {
+    pub id: u64,
+    pub bidder: Addr,
+    pub collection: Addr,
+    pub token_id: Option<String>,
+    pub price: Coin,
+    pub expires: Expiration,
+}
+
+pub struct BuyOfferIndexes<'a> {
+    pub collection: MultiIndex<'a, Addr, BuyOffer, u64>,
+    /// Collection-wide offers are indexed under an empty token id.
+    pub token: MultiIndex<'a, (Addr, String), BuyOffer, u64>,
+    pub bidder: MultiIndex<'a, Addr, BuyOffer, u64>,
+}
+
+impl<'a> IndexList<BuyOffer> for BuyOfferIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<BuyOffer>> + '_> {
+        let v: Vec<&dyn Index<BuyOffer>> = vec![&self.collection, &self.token, &self.bidder];
+        Box::new(v.into_iter())
+    }
+}
+
+pub fn buy_offers<'a>() -> IndexedMap<'a, u64, BuyOffer, BuyOfferIndexes<'a>> {
+    let indexes = BuyOfferIndexes {
+        collection: MultiIndex::new(
+            |offer: &BuyOffer| offer.collection.clone(),
+            "buy_offers",
+            "buy_offers__collection",
+        ),
+        token: MultiIndex::new(
+            |offer: &BuyOffer| {
+                (
+                    offer.collection.clone(),
+                    offer.token_id.clone().unwrap_or_default(),
+                )
+            },
+            "buy_offers",
+            "buy_offers__token",
+        ),
+        bidder: MultiIndex::new(
+            |offer: &BuyOffer| offer.bidder.clone(),
+            "buy_offers",
+            "buy_offers__bidder",
+        ),
+    };
+    IndexedMap::new("buy_offers", indexes)
+}
+
+pub struct OfferingIndexes<'a> {
+    pub collection: MultiIndex<'a, Addr, Offering, u64>,
+    pub seller: MultiIndex<'a, Addr, Offering, u64>,
+    /// Listings of a collection grouped by denom and ordered by price amount.
+    pub price: MultiIndex<'a, (Addr, String, u128), Offering, u64>,
+    /// Public listings are indexed under an empty buyer.
+    pub reserved_for: MultiIndex<'a, String, Offering, u64>,
+}
+
+impl<'a> IndexList<Offering> for OfferingIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<Offering>> + '_> {
+        let v: Vec<&dyn Index<Offering>> = vec![
+            &self.collection,
+            &self.seller,
+            &self.price,
+            &self.reserved_for,
+        ];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Fixed-price listings keyed by a global id that is never reused.
+pub fn offerings<'a>() -> IndexedMap<'a, u64, Offering, OfferingIndexes<'a>> {
+    let indexes = OfferingIndexes {
+        collection: MultiIndex::new(
+            |off: &Offering| off.contract.clone(),
+            "listings",
+            "listings__collection",
+        ),
+        seller: MultiIndex::new(
+            |off: &Offering| off.seller.clone(),
+            "listings",
+            "listings__seller",
+        ),
+        price: MultiIndex::new(
+            |off: &Offering| {
+                (off.contract.clone(), off.list_price.denom.clone(), off.list_price.amount.u128())
+            },
+            "listings",
+            "listings__price",
+        ),
+        reserved_for: MultiIndex::new(
+            |off: &Offering| {
+                off.reserved_for
+                    .as_ref()
+                    .map(Addr::to_string)
+                    .unwrap_or_default()
+            },
+            "listings",
+            "listings__reserved_for",
+        ),
+    };
+    IndexedMap::new("listings", indexes)
+}
+
+/// An NFT of any cw721 collection, given or asked for in a trade.
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct TradeNft {
+    pub collection: Addr,
+    pub token_id: String,
+}
+
+/// NFT-for-NFT trade. The offered NFTs and funds are escrowed until the
+/// counterparty accepts by sending all requested NFTs, or the trade is cancelled.
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Trade {
+    pub proposer: Addr,
+    pub counterparty: Addr,
+    pub offered_nfts: Vec<TradeNft>,
+    pub offered_funds: Vec<Coin>,
+    pub requested_nfts: Vec<TradeNft>,
+    /// requested NFTs the counterparty has sent so far, held in escrow
+    pub received_nfts: Vec<TradeNft>,
+    pub expires: Expiration,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct TradeResult {
+    pub id: u64,
+    pub proposer: Addr,
+    pub counterparty: Addr,
+    pub offered_nfts: Vec<TradeNft>,
+    pub offered_funds: Vec<Coin>,
+    pub requested_nfts: Vec<TradeNft>,
+    pub received_nfts: Vec<TradeNft>,
+    pub expires: Expiration,
+}
+
+pub struct TradeIndexes<'a> {
+    pub proposer: MultiIndex<'a, Addr, Trade, u64>,
+    pub counterparty: MultiIndex<'a, Addr, Trade, u64>,
+}
+
+impl<'a> IndexList<Trade> for TradeIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<Trade>> + '_> {
+        let v: Vec<&dyn Index<Trade>> = vec![&self.proposer, &self.counterparty];
+        Box::new(v.into_iter())
+    }
+}
+
+pub fn trades<'a>() -> IndexedMap<'a, u64, Trade, TradeIndexes<'a>> {
+    let indexes = TradeIndexes {
+        proposer: MultiIndex::new(
+            |trade: &Trade| trade.proposer.clone(),
+            "trades",
+            "trades__proposer",
+        ),
+        counterparty: MultiIndex::new(
+            |trade: &Trade| trade.counterparty.clone(),
+            "trades",
+            "trades__counterparty",
+        ),
+    };
+    IndexedMap::new("trades", indexes)
+}
 
 pub const STATE: Item<State> = Item::new("state");
-pub const OFFERINGS: Map<(&str,&str), Offering> = Map::new("offerings");
-pub const SALEHISTORY : Map<(&str,&str),SaleHistoryInfo> = Map::new("sale history");
+pub const DENOMS: Map<&str, DenomInfo> = Map::new("denoms");
+/// Marketplace fees collected per denom and not yet withdrawn or distributed.
+pub const ACCRUED_FEES: Map<&str, Uint128> = Map::new("accrued_fees");
+pub const FEE_RECIPIENTS: Item<Vec<AdminInfo>> = Item::new("fee_recipients");
+/// Discounted fee of partner collections.
+pub const COLLECTION_FEES: Map<&Addr, Decimal> = Map::new("collection_fees");
+pub const FEE_TIERS: Item<Vec<FeeTier>> = Item::new("fee_tiers");
+/// Cumulative sale volume of a seller per denom, for the fee tiers.
+pub const SELLER_VOLUME: Map<(&str, &str), Uint128> = Map::new("seller_volume");
 pub const COLLECTIONINFO : Map<&str,CollectionInfo> = Map::new("collection_info");
+/// Admin override of the factory check, `true` to allow and `false` to deny.
+pub const COLLECTION_ACCESS: Map<&Addr, bool> = Map::new("collection_access");
+/// Collections registered by the admin as answering `RoyaltyInfo`.
+pub const ROYALTY_COLLECTIONS: Map<&Addr, bool> = Map::new("royalty_collections");
+pub const AUCTIONS: Map<(&str, u64), Auction> = Map::new("auctions");
+pub const AUCTION_COUNT: Item<u64> = Item::new("auction_count");
+pub const BUY_OFFER_COUNT: Item<u64> = Item::new("buy_offer_count");
+pub const OFFERING_COUNT: Item<u64> = Item::new("offering_count");
+pub const TRADE_COUNT: Item<u64> = Item::new("trade_count");
+pub const SALE_COUNT: Item<u64> = Item::new("sale_count");
+pub const SALE_STATS: Map<&str, SaleStats> = Map::new("sale_stats");
+/// Buyers and sellers already counted in `SaleStats::unique_traders`.
+pub const TRADERS: Map<(&str, &str), bool> = Map::new("traders");
+/// Sale volume of a collection per `VOLUME_BUCKET_SECONDS` since the epoch.
+pub const VOLUME_BUCKETS: Map<(&str, u64), Vec<Coin>> = Map::new("volume_buckets");
+pub const VOLUME_BUCKET_SECONDS: u64 = 3600;
 
 
-pub fn increment_offerings(store: &mut dyn Storage,address:String) -> Result<u64, ContractError> {
-    let mut num = 0;
-    COLLECTIONINFO.update(store,&address ,| collection_info| -> Result<_, ContractError> {
-       if collection_info!= None{
-            let mut collection_info = collection_info.unwrap();
-            collection_info.num_offerings += 1;
-            num = collection_info.num_offerings;
-            Ok(collection_info)
-        }
-       else{
-        num = 1;
-        Ok(CollectionInfo {
-            sale_id:0 , 
-            tvl: Uint128::new(0),
-            num_offerings: 1 
-          })
-       }
+/// Counts a new open listing of a collection.
+pub fn increment_offerings(store: &mut dyn Storage, address: &str) -> StdResult<()> {
+    let mut collection_info = COLLECTIONINFO
+        .may_load(store, address)?
+        .unwrap_or(CollectionInfo {
+            sale_id: 0,
+            tvl: vec![],
+            num_offerings: 0,
+        });
+    collection_info.num_offerings += 1;
+    COLLECTIONINFO.save(store, address, &collection_info)
+}
+
+/// Counts a listing of a collection as closed, by sale or withdrawal.
+pub fn decrement_offerings(store: &mut dyn Storage, address: &str) -> StdResult<()> {
+    COLLECTIONINFO.update(store, address, |collection_info| -> StdResult<_> {
+        let mut collection_info =
+            collection_info.ok_or_else(|| StdError::not_found("CollectionInfo"))?;
+        collection_info.num_offerings = collection_info.num_offerings.saturating_sub(1);
+        Ok(collection_info)
     })?;
+    Ok(())
+}
 
-    Ok(num)
+pub fn next_sale_id(store: &mut dyn Storage) -> StdResult<u64> {
+    let id = SALE_COUNT.may_load(store)?.unwrap_or_default() + 1;
+    SALE_COUNT.save(store, &id)?;
+    Ok(id)
+}
+
+pub fn next_offering_id(store: &mut dyn Storage) -> StdResult<u64> {
+    let id = OFFERING_COUNT.may_load(store)?.unwrap_or_default() + 1;
+    OFFERING_COUNT.save(store, &id)?;
+    Ok(id)
+}
+
+pub fn next_auction_id(store: &mut dyn Storage) -> StdResult<u64> {
+    let id = AUCTION_COUNT.may_load(store)?.unwrap_or_default() + 1;
+    AUCTION_COUNT.save(store, &id)?;
+    Ok(id)
+}
+
+pub fn next_buy_offer_id(store: &mut dyn Storage) -> StdResult<u64> {
+    let id = BUY_OFFER_COUNT.may_load(store)?.unwrap_or_default() + 1;
+    BUY_OFFER_COUNT.save(store, &id)?;
+    Ok(id)
+}
+
+pub fn next_trade_id(store: &mut dyn Storage) -> StdResult<u64> {
+    let id = TRADE_COUNT.may_load(store)?.unwrap_or_default() + 1;
+    TRADE_COUNT.save(store, &id)?;
+    Ok(id)
 }
 
 pub fn get_fund(funds: Vec<Coin>, denom: String) -> Result<Coin, ContractError> {
@@ -71,6 +412,14 @@ pub fn get_fund(funds: Vec<Coin>, denom: String) -> Result<Coin, ContractError>
     Err(ContractError::InsufficientFunds {})
 }
 
+/// Adds `amount` to the entry of its denom in `coins`.
+pub fn add_coin(coins: &mut Vec<Coin>, amount: &Coin) {
+    match coins.iter_mut().find(|c| c.denom == amount.denom) {
+        Some(c) => c.amount += amount.amount,
+        None => coins.push(amount.clone()),
+    }
+}
+
 pub fn maybe_addr(api: &dyn Api, human: Option<String>) -> StdResult<Option<Addr>> {
     human.map(|x| api.addr_validate(&x)).transpose()
 }
@@ -83,15 +432,89 @@ pub struct SaleHistoryInfo {
     pub to: String,
     pub denom:String,
     pub amount:Uint128,
+    pub royalty_amount:Uint128,
+    pub fee_amount:Uint128,
     pub time : u64,
     pub nft_address:String,
     pub token_id:String
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SaleResult {
+    pub id: u64,
+    pub from: String,
+    pub to: String,
+    pub denom: String,
+    pub amount: Uint128,
+    pub royalty_amount: Uint128,
+    pub fee_amount: Uint128,
+    pub time: u64,
+    pub nft_address: String,
+    pub token_id: String,
+}
+
+pub struct SaleIndexes<'a> {
+    pub collection: MultiIndex<'a, String, SaleHistoryInfo, u64>,
+    pub from: MultiIndex<'a, String, SaleHistoryInfo, u64>,
+    pub to: MultiIndex<'a, String, SaleHistoryInfo, u64>,
+    pub token: MultiIndex<'a, (String, String), SaleHistoryInfo, u64>,
+}
+
+impl<'a> IndexList<SaleHistoryInfo> for SaleIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<SaleHistoryInfo>> + '_> {
+        let v: Vec<&dyn Index<SaleHistoryInfo>> =
+            vec![&self.collection, &self.from, &self.to, &self.token];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Completed sales keyed by a global id, so newer sales sort last.
+pub fn sales<'a>() -> IndexedMap<'a, u64, SaleHistoryInfo, SaleIndexes<'a>> {
+    let indexes = SaleIndexes {
+        collection: MultiIndex::new(
+            |sale: &SaleHistoryInfo| sale.nft_address.clone(),
+            "sales",
+            "sales__collection",
+        ),
+        from: MultiIndex::new(
+            |sale: &SaleHistoryInfo| sale.from.clone(),
+            "sales",
+            "sales__from",
+        ),
+        to: MultiIndex::new(
+            |sale: &SaleHistoryInfo| sale.to.clone(),
+            "sales",
+            "sales__to",
+        ),
+        token: MultiIndex::new(
+            |sale: &SaleHistoryInfo| (sale.nft_address.clone(), sale.token_id.clone()),
+            "sales",
+            "sales__token",
+        ),
+    };
+    IndexedMap::new("sales", indexes)
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct CollectionInfo{
     pub sale_id : u64,
-    pub tvl: Uint128,
+    pub tvl: Vec<Coin>,
     pub num_offerings: u64,
 }
+
+/// Running sale aggregates of a collection, kept alongside `CollectionInfo`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct SaleStats {
+    pub last_sale_price: Option<Coin>,
+    /// Distinct buyers and sellers of the collection.
+    pub unique_traders: u64,
+    pub volumes: Vec<DenomVolume>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DenomVolume {
+    pub denom: String,
+    pub volume: Uint128,
+    pub sales: u64,
+}
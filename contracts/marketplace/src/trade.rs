@@ -0,0 +1,499 @@
+use cosmwasm_std::{
+    Addr, Coin, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Order, Response, StdResult,
+};
+use cw_storage_plus::Bound;
+use cw_utils::Expiration;
+
+use crate::contract::{
+    check_denom, payment_msg, query_nft_owner, transfer_nft_msg, DEFAULT_LIMIT, MAX_LIMIT,
+};
+use crate::error::ContractError;
+use crate::msg::NftItem;
+use crate::state::{next_trade_id, trades, Trade, TradeNft, TradeResult};
+
+#[allow(clippy::too_many_arguments)]
+pub fn execute_propose_trade(
+    deps: DepsMut,
+    env: Env,
+    proposer: Addr,
+    funds: Vec<Coin>,
+    counterparty: String,
+    offered_nfts: Vec<NftItem>,
+    requested_nfts: Vec<NftItem>,
+    expires: Expiration,
+) -> Result<Response, ContractError> {
+    let counterparty = deps.api.addr_validate(&counterparty)?;
+    if counterparty == proposer || offered_nfts.is_empty() || requested_nfts.is_empty() {
+        return Err(ContractError::InvalidTrade {});
+    }
+    if has_duplicates(&offered_nfts) || has_duplicates(&requested_nfts) {
+        return Err(ContractError::InvalidTrade {});
+    }
+    if expires.is_expired(&env.block) {
+        return Err(ContractError::InvalidExpiration {});
+    }
+    for fund in &funds {
+        check_denom(deps.storage, &fund.denom)?;
+    }
+
+    let offered_nfts = trade_nfts(deps.as_ref(), offered_nfts)?;
+    let requested_nfts = trade_nfts(deps.as_ref(), requested_nfts)?;
+
+    // pull the offered NFTs into escrow, the same way `ListBatch` does
+    let mut messages = vec![];
+    for nft in &offered_nfts {
+        let owner = query_nft_owner(deps.as_ref(), &nft.collection, &nft.token_id)?;
+        if owner.owner != proposer {
+            return Err(ContractError::Unauthorized {});
+        }
+        messages.push(transfer_nft_msg(
+            &nft.collection,
+            env.contract.address.as_str(),
+            &nft.token_id,
+        )?);
+    }
+
+    let trade = Trade {
+        proposer,
+        counterparty,
+        offered_nfts,
+        offered_funds: funds.into_iter().filter(|fund| !fund.amount.is_zero()).collect(),
+        requested_nfts,
+        received_nfts: vec![],
+        expires,
+    };
+    let id = next_trade_id(deps.storage)?;
+    trades().save(deps.storage, id, &trade)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "propose_trade")
+        .add_attribute("trade_id", id.to_string())
+        .add_attribute("proposer", trade.proposer)
+        .add_attribute("counterparty", trade.counterparty)
+        .add_messages(messages))
+}
+
+/// `ReceiveNft` of a requested NFT from the counterparty. The NFT stays in escrow
+/// until the last requested NFT arrives, then both sides are paid out.
+pub fn execute_accept_trade(
+    deps: DepsMut,
+    env: Env,
+    collection: Addr,
+    sender: String,
+    token_id: String,
+    trade_id: u64,
+) -> Result<Response, ContractError> {
+    let mut trade = trades().load(deps.storage, trade_id)?;
+    if trade.counterparty != sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    if trade.expires.is_expired(&env.block) {
+        return Err(ContractError::TradeExpired {});
+    }
+    let nft = TradeNft { collection, token_id };
+    if !trade.requested_nfts.contains(&nft) || trade.received_nfts.contains(&nft) {
+        return Err(ContractError::NftNotRequested {});
+    }
+    trade.received_nfts.push(nft);
+
+    let response = Response::new()
+        .add_attribute("action", "accept_trade")
+        .add_attribute("trade_id", trade_id.to_string())
+        .add_attribute("proposer", trade.proposer.clone())
+        .add_attribute("counterparty", trade.counterparty.clone());
+    if trade.received_nfts.len() < trade.requested_nfts.len() {
+        trades().save(deps.storage, trade_id, &trade)?;
+        return Ok(response.add_attribute("received", trade.received_nfts.len().to_string()));
+    }
+
+    // everything has arrived, swap the two escrows
+    trades().remove(deps.storage, trade_id)?;
+    let mut messages = vec![];
+    for nft in &trade.received_nfts {
+        messages.push(transfer_nft_msg(&nft.collection, trade.proposer.as_str(), &nft.token_id)?);
+    }
+    messages.extend(release_escrow(deps.as_ref(), &trade, &trade.counterparty)?);
+
+    Ok(response.add_attribute("completed", "true").add_messages(messages))
+}
+
+pub fn execute_cancel_trade(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    trade_id: u64,
+) -> Result<Response, ContractError> {
+    let trade = trades().load(deps.storage, trade_id)?;
+    let participant = info.sender == trade.proposer || info.sender == trade.counterparty;
+    if !participant && !trade.expires.is_expired(&env.block) {
+        return Err(ContractError::Unauthorized {});
+    }
+    trades().remove(deps.storage, trade_id)?;
+
+    let mut messages = release_escrow(deps.as_ref(), &trade, &trade.proposer)?;
+    for nft in &trade.received_nfts {
+        messages.push(transfer_nft_msg(
+            &nft.collection,
+            trade.counterparty.as_str(),
+            &nft.token_id,
+        )?);
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_trade")
+        .add_attribute("trade_id", trade_id.to_string())
+        .add_attribute("cancelled_by", info.sender)
+        .add_messages(messages))
+}
+
+/// Sends the escrowed NFTs and funds of a trade to `recipient`.
+fn release_escrow(deps: Deps, trade: &Trade, recipient: &Addr) -> StdResult<Vec<CosmosMsg>> {
+    let mut messages = vec![];
+    for nft in &trade.offered_nfts {
+        messages.push(transfer_nft_msg(&nft.collection, recipient.as_str(), &nft.token_id)?);
+    }
+    for fund in &trade.offered_funds {
+        messages.push(payment_msg(deps.storage, recipient.as_str(), fund.clone())?);
+    }
+    Ok(messages)
+}
+
+fn has_duplicates(items: &[NftItem]) -> bool {
+    items.iter().enumerate().any(|(i, item)| {
+        items[..i]
+            .iter()
+            .any(|other| other.collection == item.collection && other.token_id == item.token_id)
+    })
+}
+
+fn trade_nfts(deps: Deps, items: Vec<NftItem>) -> StdResult<Vec<TradeNft>> {
+    items
+        .into_iter()
+        .map(|item| {
+            Ok(TradeNft {
+                collection: deps.api.addr_validate(&item.collection)?,
+                token_id: item.token_id,
+            })
+        })
+        .collect()
+}
+
+pub fn query_trade(deps: Deps, trade_id: u64) -> StdResult<TradeResult> {
+    let trade = trades().load(deps.storage, trade_id)?;
+    Ok(trade_result(trade_id, trade))
+}
+
+pub fn query_trades_by_proposer(
+    deps: Deps,
+    proposer: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<TradeResult>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let proposer = deps.api.addr_validate(&proposer)?;
+
+    trades()
+        .idx
+        .proposer
+        .prefix(proposer)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(id, trade)| trade_result(id, trade)))
+        .collect()
+}
+
+pub fn query_trades_by_counterparty(
+    deps: Deps,
+    counterparty: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<TradeResult>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let counterparty = deps.api.addr_validate(&counterparty)?;
+
+    trades()
+        .idx
+        .counterparty
+        .prefix(counterparty)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(id, trade)| trade_result(id, trade)))
+        .collect()
+}
+
+fn trade_result(id: u64, trade: Trade) -> TradeResult {
+    TradeResult {
+        id,
+        proposer: trade.proposer,
+        counterparty: trade.counterparty,
+        offered_nfts: trade.offered_nfts,
+        offered_funds: trade.offered_funds,
+        requested_nfts: trade.requested_nfts,
+        received_nfts: trade.received_nfts,
+        expires: trade.expires,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::{execute, instantiate, query};
+    use crate::cw721::{Cw721QueryMsg, OwnerOfResponse};
+    use crate::cw721::Cw721ReceiveMsg;
+    use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg, ReceiveNftMsg};
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info, MockQuerier};
+    use cosmwasm_std::{
+        coins, from_binary, to_binary, ContractResult, Decimal, SubMsg, SystemError,
+        SystemResult, WasmQuery,
+    };
+
+    // every token of "alpha" belongs to alice, every token of "beta" to bob
+    fn mock_owners(querier: &mut MockQuerier) {
+        querier.update_wasm(|query| match query {
+            WasmQuery::Smart { contract_addr, msg } => {
+                let owner = match contract_addr.as_str() {
+                    "alpha" => "alice",
+                    "beta" => "bob",
+                    _ => {
+                        return SystemResult::Err(SystemError::UnsupportedRequest {
+                            kind: "unknown".to_string(),
+                        })
+                    }
+                };
+                match from_binary(msg).unwrap() {
+                    Cw721QueryMsg::OwnerOf { .. } => SystemResult::Ok(ContractResult::Ok(
+                        to_binary(&OwnerOfResponse {
+                            owner: owner.to_string(),
+                            approvals: vec![],
+                        })
+                        .unwrap(),
+                    )),
+                    // no royalty support
+                    _ => SystemResult::Ok(ContractResult::Err(
+                        "unknown variant `royalty_info`".to_string(),
+                    )),
+                }
+            }
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "unknown".to_string(),
+            }),
+        });
+    }
+
+    fn setup(deps: DepsMut) {
+        let msg = InstantiateMsg {
+            fee: Decimal::percent(2),
+            max_fee: Decimal::percent(10),
+            fee_timelock: 86400,
+            denom: "earth".to_string(),
+            factory: None,
+        };
+        instantiate(deps, mock_env(), mock_info("creator", &[]), msg).unwrap();
+    }
+
+    fn nft(collection: &str, token_id: &str) -> NftItem {
+        NftItem {
+            collection: collection.to_string(),
+            token_id: token_id.to_string(),
+        }
+    }
+
+    // `token_id` of `collection` sent by `sender` to accept trade `trade_id`
+    fn accept_msg(sender: &str, token_id: &str, trade_id: u64) -> ExecuteMsg {
+        ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: sender.to_string(),
+            token_id: token_id.to_string(),
+            msg: to_binary(&ReceiveNftMsg::AcceptTrade { trade_id }).unwrap(),
+        })
+    }
+
+    fn propose_msg(expires: Expiration) -> ExecuteMsg {
+        ExecuteMsg::ProposeTrade {
+            counterparty: "bob".to_string(),
+            offered_nfts: vec![nft("alpha", "1")],
+            requested_nfts: vec![nft("beta", "7")],
+            expires,
+        }
+    }
+
+    #[test]
+    fn propose_and_accept_trade() {
+        let mut deps = mock_dependencies();
+        mock_owners(&mut deps.querier);
+        setup(deps.as_mut());
+
+        // bob doesn't own anything in alpha
+        let msg = ExecuteMsg::ProposeTrade {
+            counterparty: "alice".to_string(),
+            offered_nfts: vec![nft("alpha", "1")],
+            requested_nfts: vec![nft("beta", "7")],
+            expires: Expiration::Never {},
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("bob", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let info = mock_info("alice", &coins(50, "earth"));
+        let res = execute(deps.as_mut(), mock_env(), info, propose_msg(Expiration::Never {}))
+            .unwrap();
+        assert_eq!(res.messages.len(), 1);
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::GetTradesByCounterparty {
+                counterparty: "bob".to_string(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+        let trades: Vec<TradeResult> = from_binary(&res).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].proposer, "alice");
+        assert_eq!(trades[0].offered_funds, coins(50, "earth"));
+
+        let beta = mock_info("beta", &[]);
+        let msg = accept_msg("alice", "7", 1);
+        let err = execute(deps.as_mut(), mock_env(), beta.clone(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let msg = accept_msg("bob", "8", 1);
+        let err = execute(deps.as_mut(), mock_env(), beta.clone(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::NftNotRequested {}));
+        // the right token id from another collection
+        let msg = accept_msg("bob", "7", 1);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alpha", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::NftNotRequested {}));
+
+        // bob's token goes to alice, the escrowed token and funds go to bob
+        let res = execute(deps.as_mut(), mock_env(), beta, accept_msg("bob", "7", 1)).unwrap();
+        assert_eq!(res.messages.len(), 3);
+        assert!(query_trade(deps.as_ref(), 1).is_err());
+    }
+
+    #[test]
+    fn requested_nfts_are_escrowed_until_all_arrive() {
+        let mut deps = mock_dependencies();
+        mock_owners(&mut deps.querier);
+        setup(deps.as_mut());
+
+        let msg = ExecuteMsg::ProposeTrade {
+            counterparty: "bob".to_string(),
+            offered_nfts: vec![nft("alpha", "1")],
+            requested_nfts: vec![nft("beta", "7"), nft("beta", "8")],
+            expires: Expiration::Never {},
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg.clone()).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+
+        let beta = mock_info("beta", &[]);
+        let res = execute(deps.as_mut(), mock_env(), beta.clone(), accept_msg("bob", "8", 1)).unwrap();
+        assert!(res.messages.is_empty());
+        assert_eq!(query_trade(deps.as_ref(), 1).unwrap().received_nfts.len(), 1);
+        let err = execute(deps.as_mut(), mock_env(), beta.clone(), accept_msg("bob", "8", 1))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NftNotRequested {}));
+
+        // the last one completes the swap: two NFTs to alice, one to bob
+        let res = execute(deps.as_mut(), mock_env(), beta.clone(), accept_msg("bob", "7", 1)).unwrap();
+        assert_eq!(res.messages.len(), 3);
+        assert!(query_trade(deps.as_ref(), 1).is_err());
+
+        // cancelling a half accepted trade returns both escrows
+        execute(deps.as_mut(), mock_env(), beta, accept_msg("bob", "7", 2)).unwrap();
+        let msg = ExecuteMsg::CancelTrade { trade_id: 2 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(res.messages, vec![
+            SubMsg::new(transfer_nft_msg(&Addr::unchecked("alpha"), "alice", "1").unwrap()),
+            SubMsg::new(transfer_nft_msg(&Addr::unchecked("beta"), "bob", "7").unwrap()),
+        ]);
+    }
+
+    #[test]
+    fn invalid_trades_are_rejected() {
+        let mut deps = mock_dependencies();
+        mock_owners(&mut deps.querier);
+        setup(deps.as_mut());
+
+        let msg = ExecuteMsg::ProposeTrade {
+            counterparty: "bob".to_string(),
+            offered_nfts: vec![nft("alpha", "1")],
+            requested_nfts: vec![],
+            expires: Expiration::Never {},
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTrade {}));
+
+        let msg = ExecuteMsg::ProposeTrade {
+            counterparty: "alice".to_string(),
+            offered_nfts: vec![nft("alpha", "1")],
+            requested_nfts: vec![nft("alpha", "2")],
+            expires: Expiration::Never {},
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTrade {}));
+
+        // the same NFT twice on one side
+        let msg = ExecuteMsg::ProposeTrade {
+            counterparty: "bob".to_string(),
+            offered_nfts: vec![nft("alpha", "1"), nft("alpha", "1")],
+            requested_nfts: vec![nft("beta", "7")],
+            expires: Expiration::Never {},
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTrade {}));
+        let msg = ExecuteMsg::ProposeTrade {
+            counterparty: "bob".to_string(),
+            offered_nfts: vec![nft("alpha", "1")],
+            requested_nfts: vec![nft("beta", "7"), nft("beta", "7")],
+            expires: Expiration::Never {},
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTrade {}));
+
+        let expired = Expiration::AtHeight(mock_env().block.height);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), propose_msg(expired))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidExpiration {}));
+
+        let info = mock_info("alice", &coins(50, "mars"));
+        let err = execute(deps.as_mut(), mock_env(), info, propose_msg(Expiration::Never {}))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::WrongDenom {}));
+    }
+
+    #[test]
+    fn cancel_and_expire_trade() {
+        let mut deps = mock_dependencies();
+        mock_owners(&mut deps.querier);
+        setup(deps.as_mut());
+
+        let expires = Expiration::AtHeight(mock_env().block.height + 10);
+        let info = mock_info("alice", &coins(50, "earth"));
+        execute(deps.as_mut(), mock_env(), info.clone(), propose_msg(expires)).unwrap();
+        execute(deps.as_mut(), mock_env(), info, propose_msg(expires)).unwrap();
+
+        // the counterparty can decline, which returns the escrow to the proposer
+        let msg = ExecuteMsg::CancelTrade { trade_id: 1 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("carol", &[]), msg.clone())
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let res = execute(deps.as_mut(), mock_env(), mock_info("bob", &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+
+        let mut env = mock_env();
+        env.block.height += 10;
+        let msg = accept_msg("bob", "7", 2);
+        let err = execute(deps.as_mut(), env.clone(), mock_info("beta", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::TradeExpired {}));
+
+        // once expired, anyone can clean the trade up
+        let msg = ExecuteMsg::CancelTrade { trade_id: 2 };
+        execute(deps.as_mut(), env, mock_info("carol", &[]), msg).unwrap();
+
+        let trades =
+            query_trades_by_proposer(deps.as_ref(), "alice".to_string(), None, None).unwrap();
+        assert!(trades.is_empty());
+    }
+}
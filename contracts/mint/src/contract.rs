@@ -1,17 +1,20 @@
 use cosmwasm_std::{
     entry_point, to_binary,   CosmosMsg, Deps, DepsMut,Binary,SubMsg,
-    Env, MessageInfo, Response, StdResult, Uint128, WasmMsg,BankMsg,Coin, ReplyOn,Reply
+    Env, MessageInfo, Response, StdError, StdResult, Storage, Uint128, WasmMsg,BankMsg,Coin, ReplyOn,Reply
 };
 
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg,Extension, InstantiateMsg, QueryMsg, WhiteUserInfo, MintMsg};
+use crate::msg::{
+    BeaconExecuteMsg, ExecuteMsg, Extension, InstantiateMsg, QueryMsg, RandomnessCallback,
+    WhiteUserInfo, MintMsg, PhaseResponse, AllowlistProof, AllowlistProofResponse,
+};
 use crate::state::{
-    CONFIG,State,  USERINFO, WHITEUSERS, CW721_ADDRESS, 
+    CONFIG,State,  USERINFO, WHITEUSERS, CW721_ADDRESS, BEACON, MINT_JOB_COUNT, PENDING_MINTS,
+    PendingMint, MintQuota, MINTABLE_COUNT, take_mintable_id, MintPhase, PHASES, PHASE_MINTED,
+    PHASE_USER_MINTS, ALLOWLISTS, MERKLE_ROOTS, MERKLE_MINTS,
 };
-use crate::rand::{sha_256, Prng};
-
-use rand::{RngCore, SeedableRng};
-use rand_chacha::ChaChaRng;
+use crate::merkle::{leaf_hash, verify_proof};
+use crate::rand::Prng;
 
 use crate::msg::Cw721BaseExecuteMsg;
 use cw2::{set_contract_version};
@@ -22,9 +25,13 @@ const CONTRACT_NAME: &str = "crates.io:sg-minter";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 
-const MAX_TOKEN_LIMIT : Uint128 = Uint128::new(10000);
+const MAX_TOKEN_LIMIT : Uint128 = Uint128::new(1000000);
 const MAX_PER_ADDRESS_LIMIT : Uint128 = Uint128::new(100);
 const INSTANTIATE_CW721_REPLY_ID : u64 = 1;
+/// Seconds the beacon has to answer before a pending mint can be retried or
+/// cancelled.
+const PENDING_MINT_TIMEOUT: u64 = 3600;
+const CONTENT_TYPES: [&str; 3] = ["ai_nft", "language_processing", "syntetic_media"];
 
 #[entry_point]
 pub fn instantiate(
@@ -37,8 +44,7 @@ pub fn instantiate(
 
     let creator = msg.cw721_instantiate_msg.admin.clone();
     let message = msg.cw721_instantiate_msg.mint_info.clone().unwrap();
-    let create_collection_address = info.sender.to_string();
-    
+
     //check if the total_supply is more than zero and less than max token_limit
     if message.total_supply == Uint128::zero() || message.total_supply > MAX_TOKEN_LIMIT {
         return Err(ContractError::InvalidNumTokens {
@@ -56,10 +62,17 @@ pub fn instantiate(
         });
     }
 
-    if msg.content_type != "ai_nft".to_string() && msg.content_type != "language_processing".to_string() && msg.content_type != "syntetic_media".to_string(){
+    if !CONTENT_TYPES.contains(&msg.content_type.as_str()) {
         return Err(ContractError::InvalidContentType {  })
     }
 
+    let max_per_tx = message.max_per_tx.unwrap_or(message.per_address_limit.u128() as u32);
+    if max_per_tx == 0 || max_per_tx > MAX_PER_ADDRESS_LIMIT.u128() as u32 {
+        return Err(ContractError::InvalidMintQuantity {
+            max: MAX_PER_ADDRESS_LIMIT.u128() as u32,
+        });
+    }
+
     // if current time is beyond the provided start time return error
     let current_time = env.block.time.seconds();
     if current_time > message.start_mint_time {
@@ -69,12 +82,6 @@ pub fn instantiate(
         ));
     }
 
-    let total_num = Uint128::u128(&message.total_supply) as u32;
-    let mut mintable_token_list:Vec<u32> = Vec::new();      
-    for i in 1 .. total_num+1{
-        mintable_token_list.push(i);
-    }
-    
     let state = State {
         admin:creator.clone(),
         base_token_uri: message.base_token_uri,
@@ -82,10 +89,10 @@ pub fn instantiate(
         mint_count:Uint128::zero(),
         start_mint_time: message.start_mint_time,
         per_address_limit: message.per_address_limit,
+        max_per_tx,
         public_price: message.public_price,
         private_price:message.private_price,
         mint_flag:true,
-        enable_token_id:Some(mintable_token_list),
         is_public_mint:message.is_public_mint,
         nft_base_name:message.nft_base_name.clone(),
         base_image_uri:message.base_image_uri,
@@ -93,23 +100,32 @@ pub fn instantiate(
         content_type: msg.content_type,
     };
     CONFIG.save(deps.storage, &state)?;
+    if let Some(phases) = message.phases {
+        check_phases(&phases)?;
+        PHASES.save(deps.storage, &phases)?;
+    }
+    MINTABLE_COUNT.save(deps.storage, &(state.total_supply.u128() as u32))?;
+
+    if let Some(beacon) = msg.randomness_beacon {
+        BEACON.save(deps.storage, &deps.api.addr_validate(&beacon)?.to_string())?;
+    }
+
 
-    
     // message to instantiate the new nft collection contract
     let init_msg : SubMsg = SubMsg{
-            msg: WasmMsg::Instantiate { 
-            admin: Some(creator), 
-            code_id: msg.cw721_code_id, 
+            msg: WasmMsg::Instantiate {
+            admin: Some(creator),
+            code_id: msg.cw721_code_id,
             msg: to_binary(&msg.cw721_instantiate_msg)?,
-            funds: info.funds, 
-            label: message.nft_base_name 
+            funds: info.funds,
+            label: message.nft_base_name
         }.into(),
         id:INSTANTIATE_CW721_REPLY_ID,
         gas_limit:None,
         reply_on:ReplyOn::Success
     } ;
 
-   
+
     Ok(Response::new()
         .add_attribute("action", "instantiate")
         .add_attribute("contract_name", CONTRACT_NAME)
@@ -117,7 +133,7 @@ pub fn instantiate(
         .add_attribute("sender", info.sender)
         .add_submessage(init_msg)
     )
-       
+
 }
 
 #[entry_point]
@@ -128,25 +144,39 @@ pub fn execute(
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
-        ExecuteMsg::Mint { } => execute_mint(deps, env, info),
-        ExecuteMsg::SetConfig { config } => execute_set_config(deps, info,config),
+        ExecuteMsg::Mint { quantity, proof } => execute_mint(deps, env, info, quantity, proof),
+        ExecuteMsg::ReceiveRandomness { callback } => execute_receive_randomness(deps, info, callback),
+        ExecuteMsg::SetConfig { config } => execute_set_config(deps, info,*config),
         ExecuteMsg::SetMintFlag { flag } => execute_set_flag(deps, info,flag),
+        ExecuteMsg::SetRandomnessBeacon { address } => execute_set_randomness_beacon(deps, info, address),
+        ExecuteMsg::RetryPendingMint { job_id } => execute_retry_pending_mint(deps, env, info, job_id),
+        ExecuteMsg::CancelPendingMint { job_id } => execute_cancel_pending_mint(deps, env, info, job_id),
         ExecuteMsg::AddWhiteUsers {  white_users } => execute_add_white_users(deps, info, white_users),
-        ExecuteMsg::ChangeSaleType { is_public_mint } => execute_change_sale_type(deps, info, is_public_mint)
-    }                                  
+        ExecuteMsg::ChangeSaleType { is_public_mint } => execute_change_sale_type(deps, info, is_public_mint),
+        ExecuteMsg::SetPhases { phases } => execute_set_phases(deps, info, phases),
+        ExecuteMsg::AddAllowlistUsers { allowlist_id, addresses } => execute_update_allowlist(deps, info, allowlist_id, addresses, true),
+        ExecuteMsg::RemoveAllowlistUsers { allowlist_id, addresses } => execute_update_allowlist(deps, info, allowlist_id, addresses, false),
+        ExecuteMsg::SetMerkleRoot { allowlist_id, root } => execute_set_merkle_root(deps, info, allowlist_id, root)
+    }
 }
 
+/// Takes payment and reserves `quantity` tokens, then asks the randomness beacon to
+/// pick their ids. The payment is passed on to the admin once they are minted. The NFTs are minted when the beacon calls back with
+/// `ReceiveRandomness`, so nobody can tell at mint time which ids they will get.
 fn execute_mint(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
+    quantity: u32,
+    proof: Option<AllowlistProof>,
 ) -> Result<Response, ContractError> {
     let sender = info.sender.to_string();
 
     let state = CONFIG.load(deps.storage)?;
+    let beacon = BEACON.may_load(deps.storage)?.ok_or(ContractError::NoRandomnessBeacon {})?;
 
     //check if mint is enabled
-    if state.mint_flag == false{
+    if !state.mint_flag {
         return Err(ContractError::MintDisabled {  });
     }
 
@@ -155,145 +185,249 @@ fn execute_mint(
         return Err(ContractError::MintNotStarted {});
     }
 
-    //check if all nfts are minted 
-    if state.mint_count >= state.total_supply {
-        return Err(ContractError::SoldOut {});
+    if quantity == 0 || quantity > state.max_per_tx {
+        return Err(ContractError::InvalidMintQuantity { max: state.max_per_tx });
     }
+    let count = Uint128::from(quantity);
 
-    let user_mint_count =  WHITEUSERS.may_load(deps.storage, &sender)?;
-
-    //check the white_list_user if this is not public mint
-    if !state.is_public_mint && user_mint_count == None{
-        return Err(ContractError::NotWhiteListedUser{})
+    //check if enough nfts are left
+    if state.mint_count + count > state.total_supply {
+        return Err(ContractError::SoldOut {});
     }
 
-    //check the per_wallet limit minting number and reset the current mint count of the user
-    if state.is_public_mint{
-        let user_mint_count = USERINFO.may_load(deps.storage, &sender)?;
-        if user_mint_count == None {
-            USERINFO.save(deps.storage, &sender, &Uint128::new(1))?;
-        } else {
-            if user_mint_count.unwrap() >= state.per_address_limit {
+    let phases = PHASES.may_load(deps.storage)?.unwrap_or_default();
+    let (price, quota) = if phases.is_empty() {
+        //check the per_wallet limit minting number and reset the current mint count of the user
+        if state.is_public_mint{
+            let user_mint_count = USERINFO.may_load(deps.storage, &sender)?.unwrap_or_default();
+            if user_mint_count + count > state.per_address_limit {
                 return Err(ContractError::MaxPerAddressLimitExceeded{});
-            } else {
-                USERINFO.update(deps.storage, &sender, 
-                    |user_mint_count|->StdResult<_>{
-                        let mut user_mint_count = user_mint_count.unwrap();
-                        user_mint_count = user_mint_count + Uint128::new(1);
-                        Ok(user_mint_count)
-                    })?;
             }
+            USERINFO.save(deps.storage, &sender, &(user_mint_count + count))?;
+        } else{
+            //check the white_list_user if this is not public mint
+            let user_mint_count = WHITEUSERS
+                .may_load(deps.storage, &sender)?
+                .ok_or(ContractError::NotWhiteListedUser {})?;
+            if user_mint_count < count {
+                return Err(ContractError::MaxPerAddressLimitExceeded {  });
+            }
+            WHITEUSERS.save(deps.storage, &sender, &(user_mint_count - count))?;
         }
-    } else{
-        let user_mint_count =  WHITEUSERS.load(deps.storage, &sender.clone())?;
-        if user_mint_count == Uint128::zero(){
-            return Err(ContractError::MaxPerAddressLimitExceeded {  });
+        if state.is_public_mint {
+            (state.public_price, MintQuota::Public)
         } else {
-            WHITEUSERS.update(deps.storage, &sender.clone(), 
-            |white_user_info| -> StdResult<_>{
-                let mut white_user_info =  white_user_info.unwrap();
-                white_user_info = white_user_info - Uint128::new(1);
-                Ok(white_user_info)
-            })?;
+            (state.private_price, MintQuota::WhiteUser)
         }
-    }
-
-    //get mint index for token_id and change collection info
-    let  mint_index:Uint128;
-
-    let mut enable_token_id = state.enable_token_id.unwrap();
-    let count = enable_token_id.len();
-    let prng_seed: Vec<u8> = sha_256(base64::encode("entropy").as_bytes()).to_vec();
-    let random_seed = new_entropy(&info,&env, prng_seed.as_ref(), prng_seed.as_ref());
-    let mut rng = ChaChaRng::from_seed(random_seed);
-    let  rand_num = (rng.next_u32() % (count as u32)) as usize ;
-    let rand = enable_token_id[rand_num];
-    mint_index = Uint128::new(rand as u128);
-    enable_token_id.remove(rand_num);
-
-    // updated mintable token ids
-    CONFIG.update(deps.storage, |mut state|->StdResult<_>{  
-            state.enable_token_id = Some(enable_token_id);
-            Ok(state)
-    })?;
-
-
-    //increase the total mint count by one
-    CONFIG.update(deps.storage, |mut state|->StdResult<_>{  
-            state.mint_count = state.mint_count + Uint128::new(1);
-            Ok(state)
-    })?;
+    } else {
+        let now = env.block.time.seconds();
+        let phase = phases
+            .into_iter()
+            .find(|phase| phase.is_active(now))
+            .ok_or(ContractError::NoActivePhase {})?;
+        let merkle_allowlist = mint_in_phase(deps.storage, &phase, &sender, count, proof)?;
+        (phase.price, MintQuota::Phase { name: phase.name, merkle_allowlist })
+    };
 
-    let token_id = [state.nft_base_name,mint_index.to_string()].join(".");
-    let collection_address = CW721_ADDRESS.load(deps.storage)?;
+    let mut price = price;
+    price.amount = price.amount.checked_mul(count).map_err(StdError::from)?;
 
-    //mint message
-    let mut messages :Vec<CosmosMsg> = vec![];
-    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: collection_address,
-            msg: to_binary(&Cw721BaseExecuteMsg::Mint(MintMsg {
-                //::<Metadata>
-                token_id: token_id.clone(),
-                owner: sender.clone(),
-                content_type: state.content_type,
-                token_uri: Some([[state.base_token_uri,mint_index.to_string()].join(""),"json".to_string()].join(".")),
-                extension:  Extension{
-                    minter: sender.clone(),
-                    image_url: [[state.base_image_uri,mint_index.to_string()].join(""),"png".to_string()].join(".")
-                }
-        }))?,
-        funds: vec![],
-    }));
-
-    let denom :String;
-    let unit_price:Uint128;
-  
-    if state.is_public_mint{
-        denom = state.public_price.denom;
-        unit_price = state.public_price.amount;
-    }else{
-        denom = state.private_price.denom;
-        unit_price = state.private_price.amount;
-    }
     //funds the users sent
     let amount=  info
         .funds
         .iter()
-        .find(|c| c.denom == denom)
-        .map(|c| Uint128::from(c.amount))
+        .find(|c| c.denom == price.denom)
+        .map(|c| c.amount)
         .unwrap_or_else(Uint128::zero);
-    
-    //check the price(for the owner the price is zero) 
+
+    //check the price(for the owner the price is zero)
     if sender == state.admin {
         if amount > Uint128::zero(){
             return Err(ContractError::NotExactFunds{
                 price:Uint128::zero(),
                 sent_money: amount
-            })   
-        }
-    } else{
-        if amount != unit_price{
-            return Err(ContractError::NotExactFunds{
-                price:unit_price,
-                sent_money:amount
             })
-        }else{ messages.push(CosmosMsg::Bank(BankMsg::Send { 
-                to_address: state.admin,
-                amount: vec![Coin{
-                    denom:denom,
-                    amount:amount
-                }] })
-            )}
-    }
-   
+        }
+    } else if amount != price.amount {
+        return Err(ContractError::NotExactFunds{
+            price:price.amount,
+            sent_money:amount
+        })
+    }
+
+    //increase the total mint count, the token ids are drawn once the randomness arrives
+    CONFIG.update(deps.storage, |mut state|->StdResult<_>{
+            state.mint_count += count;
+            Ok(state)
+    })?;
+
+    //the payment stays here until the tokens are minted
+    let job_id = next_mint_job_id(deps.storage)?;
+    let pending = PendingMint {
+        owner: sender.clone(),
+        quantity,
+        payment: Coin { denom: price.denom, amount },
+        beacon: beacon.clone(),
+        requested_at: env.block.time.seconds(),
+        quota,
+    };
+    PENDING_MINTS.save(deps.storage, &job_id, &pending)?;
+
+    let messages: Vec<CosmosMsg> = vec![CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: beacon,
+        msg: to_binary(&BeaconExecuteMsg::GetNextRandomness { job_id: job_id.clone() })?,
+        funds: vec![],
+    })];
+
     Ok(Response::new()
         .add_messages(messages)
         .add_attribute("human_marketplace_minter", sender)
         .add_attribute("human_mint_type", "human_marketplace_admin_mint")
-        .add_attribute("token_id", token_id)
+        .add_attribute("quantity", quantity.to_string())
+        .add_attribute("job_id", job_id)
+    )
+}
+
+/// Checks the allowlist and limits of the active phase and counts the mint
+/// against them. Returns the merkle allowlist the mint was counted against.
+fn mint_in_phase(
+    storage: &mut dyn Storage,
+    phase: &MintPhase,
+    sender: &str,
+    count: Uint128,
+    proof: Option<AllowlistProof>,
+) -> Result<Option<u32>, ContractError> {
+    let merkle_allowlist = match (phase.allowlist_id, proof) {
+        (Some(allowlist_id), Some(proof)) => {
+            let root = MERKLE_ROOTS.may_load(storage, allowlist_id)?.ok_or(ContractError::InvalidMerkleProof {})?;
+            if !verify_proof(&root, leaf_hash(sender, proof.limit), &proof.proof) {
+                return Err(ContractError::InvalidMerkleProof {});
+            }
+            let minted = MERKLE_MINTS.may_load(storage, (allowlist_id, sender))?.unwrap_or_default();
+            if minted + count > proof.limit {
+                return Err(ContractError::MaxPerAddressLimitExceeded {});
+            }
+            MERKLE_MINTS.save(storage, (allowlist_id, sender), &(minted + count))?;
+            Some(allowlist_id)
+        }
+        (Some(allowlist_id), None) => {
+            if !ALLOWLISTS.has(storage, (allowlist_id, sender)) {
+                return Err(ContractError::NotWhiteListedUser {});
+            }
+            None
+        }
+        (None, _) => None,
+    };
+
+    let key = (phase.name.as_str(), sender);
+    let user_mint_count = PHASE_USER_MINTS.may_load(storage, key)?.unwrap_or_default();
+    if user_mint_count + count > phase.per_address_limit {
+        return Err(ContractError::MaxPerAddressLimitExceeded {});
+    }
+    PHASE_USER_MINTS.save(storage, key, &(user_mint_count + count))?;
+
+    let minted = PHASE_MINTED.may_load(storage, &phase.name)?.unwrap_or_default();
+    if phase.supply_cap.is_some_and(|cap| minted + count > cap) {
+        return Err(ContractError::SoldOut {});
+    }
+    PHASE_MINTED.save(storage, &phase.name, &(minted + count))?;
+    Ok(merkle_allowlist)
+}
+
+fn check_phases(phases: &[MintPhase]) -> Result<(), ContractError> {
+    for (i, phase) in phases.iter().enumerate() {
+        if phases[..i].iter().any(|other| other.name == phase.name) {
+            return Err(ContractError::InvalidPhases {});
+        }
+        if phase.end_time.is_some_and(|end| end <= phase.start_time) {
+            return Err(ContractError::InvalidPhases {});
+        }
+        if let Some(next) = phases.get(i + 1) {
+            match phase.end_time {
+                Some(end) if end <= next.start_time => {}
+                _ => return Err(ContractError::InvalidPhases {}),
+            }
+        }
+        if phase.per_address_limit == Uint128::zero() || phase.per_address_limit > MAX_PER_ADDRESS_LIMIT {
+            return Err(ContractError::InvalidPerAddressLimit {
+                max: MAX_PER_ADDRESS_LIMIT,
+                min: Uint128::new(1),
+                got: phase.per_address_limit,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Beacon callback. Draws the token ids of a pending mint from the published
+/// randomness and mints them to the account that paid for them.
+fn execute_receive_randomness(
+    deps: DepsMut,
+    info: MessageInfo,
+    callback: RandomnessCallback,
+) -> Result<Response, ContractError> {
+    let beacon = BEACON.may_load(deps.storage)?;
+    if beacon.as_deref() != Some(info.sender.as_str()) {
+        return Err(ContractError::Unauthorized {});
+    }
+    if callback.randomness.len() != 32 {
+        return Err(ContractError::InvalidRandomness {});
+    }
+
+    let pending = PENDING_MINTS.load(deps.storage, &callback.job_id)?;
+    PENDING_MINTS.remove(deps.storage, &callback.job_id);
+
+    let state = CONFIG.load(deps.storage)?;
+    let collection_address = CW721_ADDRESS.load(deps.storage)?;
+    let mut rng = Prng::new(callback.randomness.as_slice(), callback.job_id.as_bytes());
+
+    let mut mint_msgs: Vec<CosmosMsg> = vec![];
+    let mut token_ids: Vec<String> = vec![];
+    for _ in 0..pending.quantity {
+        let remaining = MINTABLE_COUNT.load(deps.storage)?;
+        let slot = rng.gen_index(remaining as usize) as u32;
+        let mint_index = take_mintable_id(deps.storage, slot)?;
+
+        let token_id = [state.nft_base_name.clone(),mint_index.to_string()].join(".");
+        mint_msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: collection_address.clone(),
+                msg: to_binary(&Cw721BaseExecuteMsg::Mint(MintMsg {
+                    //::<Metadata>
+                    token_id: token_id.clone(),
+                    owner: pending.owner.clone(),
+                    content_type: state.content_type.clone(),
+                    token_uri: Some([[state.base_token_uri.clone(),mint_index.to_string()].join(""),"json".to_string()].join(".")),
+                    extension:  Extension{
+                        minter: pending.owner.clone(),
+                        image_url: [[state.base_image_uri.clone(),mint_index.to_string()].join(""),"png".to_string()].join(".")
+                    }
+            }))?,
+            funds: vec![],
+        }));
+        token_ids.push(token_id);
+    }
+    if !pending.payment.amount.is_zero() {
+        mint_msgs.push(CosmosMsg::Bank(BankMsg::Send {
+            to_address: state.admin,
+            amount: vec![pending.payment],
+        }));
+    }
+
+    Ok(Response::new()
+        .add_messages(mint_msgs)
+        .add_attribute("action", "receive_randomness")
+        .add_attribute("job_id", callback.job_id)
+        .add_attribute("owner", pending.owner)
+        .add_attribute("token_ids", token_ids.join(","))
     )
 }
 
+fn next_mint_job_id(storage: &mut dyn Storage) -> StdResult<String> {
+    let id = MINT_JOB_COUNT.may_load(storage)?.unwrap_or_default() + 1;
+    MINT_JOB_COUNT.save(storage, &id)?;
+    Ok(format!("mint-{}", id))
+}
+
 fn execute_set_config(
     deps: DepsMut,
     info: MessageInfo,
@@ -303,15 +437,22 @@ fn execute_set_config(
     let state = CONFIG.load(deps.storage)?;
 
     //auth check
-    if info.sender.to_string() != state.admin {
+    if info.sender != state.admin {
         return Err(ContractError::Unauthorized {});
     }
 
+    if config.max_per_tx == 0 || config.max_per_tx > MAX_PER_ADDRESS_LIMIT.u128() as u32 {
+        return Err(ContractError::InvalidMintQuantity {
+            max: MAX_PER_ADDRESS_LIMIT.u128() as u32,
+        });
+    }
+
     CONFIG.update(deps.storage,
         |mut state|->StdResult<_>{
             state.base_token_uri = config.base_token_uri;
             state.start_mint_time = config.start_mint_time;
             state.per_address_limit = config.per_address_limit;
+            state.max_per_tx = config.max_per_tx;
             state.public_price = config.public_price;
             state.private_price = config.private_price;
             state.nft_base_name = config.nft_base_name;
@@ -331,11 +472,11 @@ fn execute_set_flag(
 ) -> Result<Response, ContractError> {
    let state =CONFIG.load(deps.storage)?;
    //auth check
-   if state.admin != info.sender.to_string() {
+   if info.sender != state.admin {
         return Err(ContractError::Unauthorized {});
    }
-   
-   CONFIG.update(deps.storage, 
+
+   CONFIG.update(deps.storage,
     |mut state|->StdResult<_>{
         state.mint_flag = flag;
         Ok(state)
@@ -346,6 +487,123 @@ fn execute_set_flag(
 }
 
 
+fn execute_set_randomness_beacon(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+   let state =CONFIG.load(deps.storage)?;
+   //auth check
+   if info.sender != state.admin {
+        return Err(ContractError::Unauthorized {});
+   }
+   if BEACON.may_load(deps.storage)?.is_some() {
+        return Err(ContractError::BeaconAlreadySet {});
+   }
+
+   let beacon = deps.api.addr_validate(&address)?;
+   BEACON.save(deps.storage, &beacon.to_string())?;
+
+   Ok(Response::new()
+       .add_attribute("action", "set randomness beacon")
+       .add_attribute("beacon", beacon))
+}
+
+
+/// Loads a pending mint its minter or the admin may retry or cancel, which is
+/// only once the beacon had `PENDING_MINT_TIMEOUT` seconds to answer.
+fn load_stranded_mint(
+    deps: Deps,
+    env: &Env,
+    info: &MessageInfo,
+    job_id: &str,
+) -> Result<PendingMint, ContractError> {
+   let state = CONFIG.load(deps.storage)?;
+   let pending = PENDING_MINTS.load(deps.storage, job_id)?;
+   if info.sender != pending.owner && info.sender != state.admin {
+        return Err(ContractError::Unauthorized {});
+   }
+   if env.block.time.seconds() < pending.requested_at.saturating_add(PENDING_MINT_TIMEOUT) {
+        return Err(ContractError::PendingMintNotExpired { timeout: PENDING_MINT_TIMEOUT });
+   }
+   Ok(pending)
+}
+
+
+fn execute_retry_pending_mint(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    job_id: String,
+) -> Result<Response, ContractError> {
+   let pending = load_stranded_mint(deps.as_ref(), &env, &info, &job_id)?;
+
+   Ok(Response::new()
+       .add_message(WasmMsg::Execute {
+           contract_addr: pending.beacon.clone(),
+           msg: to_binary(&BeaconExecuteMsg::GetNextRandomness { job_id: job_id.clone() })?,
+           funds: vec![],
+       })
+       .add_attribute("action", "retry pending mint")
+       .add_attribute("job_id", job_id)
+       .add_attribute("beacon", pending.beacon))
+}
+
+
+/// Refunds a stranded mint and gives back everything it reserved: the tokens,
+/// and the per address and phase counts it was charged against.
+fn execute_cancel_pending_mint(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    job_id: String,
+) -> Result<Response, ContractError> {
+   let pending = load_stranded_mint(deps.as_ref(), &env, &info, &job_id)?;
+   PENDING_MINTS.remove(deps.storage, &job_id);
+
+   let count = Uint128::from(pending.quantity);
+   let owner = pending.owner.as_str();
+   let release = |amount: Option<Uint128>| amount.unwrap_or_default().saturating_sub(count);
+   match &pending.quota {
+        MintQuota::Public => {
+            USERINFO.update(deps.storage, owner, |amount| -> StdResult<_> { Ok(release(amount)) })?;
+        }
+        MintQuota::WhiteUser => {
+            WHITEUSERS.update(deps.storage, owner, |amount| -> StdResult<_> {
+                Ok(amount.unwrap_or_default() + count)
+            })?;
+        }
+        MintQuota::Phase { name, merkle_allowlist } => {
+            PHASE_MINTED.update(deps.storage, name, |amount| -> StdResult<_> { Ok(release(amount)) })?;
+            PHASE_USER_MINTS.update(deps.storage, (name, owner), |amount| -> StdResult<_> {
+                Ok(release(amount))
+            })?;
+            if let Some(allowlist_id) = merkle_allowlist {
+                MERKLE_MINTS.update(deps.storage, (*allowlist_id, owner), |amount| -> StdResult<_> {
+                    Ok(release(amount))
+                })?;
+            }
+        }
+   }
+   CONFIG.update(deps.storage, |mut state| -> StdResult<_> {
+        state.mint_count = state.mint_count.saturating_sub(count);
+        Ok(state)
+   })?;
+
+   let mut res = Response::new()
+       .add_attribute("action", "cancel pending mint")
+       .add_attribute("job_id", job_id)
+       .add_attribute("owner", pending.owner.clone());
+   if !pending.payment.amount.is_zero() {
+        res = res.add_message(BankMsg::Send {
+            to_address: pending.owner,
+            amount: vec![pending.payment],
+        });
+   }
+   Ok(res)
+}
+
+
 fn execute_change_sale_type(
     deps: DepsMut,
     info: MessageInfo,
@@ -353,11 +611,11 @@ fn execute_change_sale_type(
 ) -> Result<Response, ContractError> {
    let state =CONFIG.load(deps.storage)?;
    //auth check
-   if state.admin != info.sender.to_string() {
+   if info.sender != state.admin {
         return Err(ContractError::Unauthorized {});
    }
-   
-   CONFIG.update(deps.storage, 
+
+   CONFIG.update(deps.storage,
     |mut state|->StdResult<_>{
         state.is_public_mint = is_public_mint;
         Ok(state)
@@ -376,12 +634,12 @@ fn execute_add_white_users(
     white_users:Vec<WhiteUserInfo>
 ) -> Result<Response, ContractError> {
    let state =CONFIG.load(deps.storage)?;
-   
+
    //auth check
-    if state.admin != info.sender.to_string() {
+    if info.sender != state.admin {
         return Err(ContractError::Unauthorized {});
     }
-   
+
     for white_user in white_users{
         deps.api.addr_validate(&white_user.address)?;
         WHITEUSERS.save(deps.storage, &white_user.address, &white_user.wallet_limit)?;
@@ -393,6 +651,85 @@ fn execute_add_white_users(
     )
 }
 
+fn execute_set_phases(
+    deps: DepsMut,
+    info: MessageInfo,
+    phases: Vec<MintPhase>
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+
+    //auth check
+    if info.sender != state.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    check_phases(&phases)?;
+    PHASES.save(deps.storage, &phases)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set phases")
+        .add_attribute("phases", phases.len().to_string())
+    )
+}
+
+fn execute_update_allowlist(
+    deps: DepsMut,
+    info: MessageInfo,
+    allowlist_id: u32,
+    addresses: Vec<String>,
+    add: bool
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+
+    //auth check
+    if info.sender != state.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    for address in addresses {
+        if add {
+            deps.api.addr_validate(&address)?;
+            ALLOWLISTS.save(deps.storage, (allowlist_id, &address), &true)?;
+        } else {
+            ALLOWLISTS.remove(deps.storage, (allowlist_id, &address));
+        }
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", if add { "add allowlist users" } else { "remove allowlist users" })
+        .add_attribute("allowlist_id", allowlist_id.to_string())
+    )
+}
+
+fn execute_set_merkle_root(
+    deps: DepsMut,
+    info: MessageInfo,
+    allowlist_id: u32,
+    root: Option<Binary>
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+
+    //auth check
+    if info.sender != state.admin {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    match root {
+        Some(root) => {
+            if root.len() != 32 {
+                return Err(ContractError::InvalidMerkleRoot {});
+            }
+            MERKLE_ROOTS.save(deps.storage, allowlist_id, &root)?;
+        }
+        None => MERKLE_ROOTS.remove(deps.storage, allowlist_id),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set merkle root")
+        .add_attribute("allowlist_id", allowlist_id.to_string())
+    )
+}
+
 // Reply callback triggered from cw721 contract instantiation
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
@@ -415,11 +752,15 @@ pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractE
 
 
 #[entry_point]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::GetStateInfo { } => to_binary(& query_state_info(deps)?),
         QueryMsg::GetUserInfo { address } => to_binary(& query_user_info(deps,address)?),
-        QueryMsg::GetCollectionAddress { } =>  to_binary(& query_collection_address(deps)?)
+        QueryMsg::GetCollectionAddress { } =>  to_binary(& query_collection_address(deps)?),
+        QueryMsg::GetPendingMint { job_id } => to_binary(&PENDING_MINTS.may_load(deps.storage, &job_id)?),
+        QueryMsg::GetMintableCount { } => to_binary(&MINTABLE_COUNT.load(deps.storage)?),
+        QueryMsg::GetActivePhase { } => to_binary(&query_active_phase(deps, env)?),
+        QueryMsg::VerifyAllowlistProof { allowlist_id, address, proof } => to_binary(&query_verify_allowlist_proof(deps, allowlist_id, address, proof)?)
     }
 }
 
@@ -431,22 +772,39 @@ pub fn query_state_info(deps: Deps) -> StdResult<State>{
 
 pub fn query_user_info(deps: Deps, address: String) -> StdResult<Uint128>{
    let state = CONFIG.load(deps.storage)?;
-   if state.is_public_mint{
-     let user_info = USERINFO.may_load(deps.storage, &address)?;    
-     if user_info == None{
-        Ok(Uint128::zero()) 
-     } else{
-        Ok(user_info.unwrap())
-     }
-   } else{
-     let user_info = WHITEUSERS.may_load(deps.storage, &address)?; 
-     if user_info == None{
-        Ok(Uint128::zero())
-     }
-     else{
-        Ok(user_info.unwrap())
-     }
-   }
+   let user_info = if state.is_public_mint {
+       USERINFO.may_load(deps.storage, &address)?
+   } else {
+       WHITEUSERS.may_load(deps.storage, &address)?
+   };
+   Ok(user_info.unwrap_or_default())
+}
+
+pub fn query_active_phase(deps: Deps, env: Env) -> StdResult<Option<PhaseResponse>>{
+    let now = env.block.time.seconds();
+    let phases = PHASES.may_load(deps.storage)?.unwrap_or_default();
+    match phases.into_iter().enumerate().find(|(_, phase)| phase.is_active(now)) {
+        Some((id, phase)) => {
+            let id = id as u32;
+            let minted = PHASE_MINTED.may_load(deps.storage, &phase.name)?.unwrap_or_default();
+            Ok(Some(PhaseResponse { id, phase, minted }))
+        }
+        None => Ok(None),
+    }
+}
+
+pub fn query_verify_allowlist_proof(
+    deps: Deps,
+    allowlist_id: u32,
+    address: String,
+    proof: AllowlistProof
+) -> StdResult<AllowlistProofResponse>{
+    let valid = match MERKLE_ROOTS.may_load(deps.storage, allowlist_id)? {
+        Some(root) => verify_proof(&root, leaf_hash(&address, proof.limit), &proof.proof),
+        None => false,
+    };
+    let minted = MERKLE_MINTS.may_load(deps.storage, (allowlist_id, &address))?.unwrap_or_default();
+    Ok(AllowlistProofResponse { valid, limit: proof.limit, minted })
 }
 
 pub fn query_collection_address(deps: Deps) -> StdResult<String>{
@@ -454,18 +812,418 @@ pub fn query_collection_address(deps: Deps) -> StdResult<String>{
     Ok(result)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{CollectionInfo, Cw721InitMessage, MintInfo, Royalty, MINTABLE_IDS};
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, from_binary, Decimal};
+
+    fn setup(deps: DepsMut, total_supply: u128) {
+        let mint_info = MintInfo {
+            base_token_uri: "ipfs://meta/".to_string(),
+            total_supply: Uint128::new(total_supply),
+            start_mint_time: mock_env().block.time.seconds(),
+            per_address_limit: Uint128::new(5),
+            max_per_tx: Some(3),
+            phases: None,
+            public_price: coin(100, "earth"),
+            private_price: coin(50, "earth"),
+            mint_flag: true,
+            is_public_mint: true,
+            nft_base_name: "Human".to_string(),
+            base_image_uri: "ipfs://image/".to_string(),
+        };
+        let msg = InstantiateMsg {
+            cw721_instantiate_msg: Cw721InitMessage {
+                name: "Human".to_string(),
+                symbol: "HMN".to_string(),
+                minter: None,
+                admin: "admin".to_string(),
+                collection_info: CollectionInfo {
+                    title: "Human".to_string(),
+                    background_url: String::new(),
+                    logo_url: String::new(),
+                    description: String::new(),
+                },
+                mint_info: Some(mint_info),
+                royalty_info: Royalty {
+                    address: "admin".to_string(),
+                    royalty_rate: Decimal::percent(5),
+                },
+            },
+            cw721_code_id: 1,
+            content_type: "ai_nft".to_string(),
+            randomness_beacon: Some("beacon".to_string()),
+        };
+        instantiate(deps, mock_env(), mock_info("factory", &[]), msg).unwrap();
+    }
 
+    fn callback(job_id: &str, byte: u8) -> ExecuteMsg {
+        ExecuteMsg::ReceiveRandomness {
+            callback: RandomnessCallback {
+                job_id: job_id.to_string(),
+                randomness: Binary::from(vec![byte; 32]),
+            },
+        }
+    }
 
-pub fn new_entropy(info:&MessageInfo,env: &Env, seed: &[u8], entropy: &[u8]) -> [u8; 32] {
-    // 16 here represents the lengths in bytes of the block height and time.
-    let entropy_len = 16 + info.sender.to_string().len() + entropy.len();
-    let mut rng_entropy = Vec::with_capacity(entropy_len);
-    rng_entropy.extend_from_slice(&env.block.height.to_be_bytes());
-    rng_entropy.extend_from_slice(&info.sender.as_bytes());
-    rng_entropy.extend_from_slice(entropy);
+    #[test]
+    fn mint_waits_for_randomness() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), 3);
+        CW721_ADDRESS.save(deps.as_mut().storage, &"collection".to_string()).unwrap();
+
+        let info = mock_info("buyer", &coins(100, "earth"));
+        let res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Mint { quantity: 1, proof: None }).unwrap();
+        // only the randomness request, the payment is held until the tokens exist
+        assert_eq!(res.messages.len(), 1);
+        let pending: Option<PendingMint> = from_binary(
+            &query(deps.as_ref(), mock_env(), QueryMsg::GetPendingMint { job_id: "mint-1".to_string() })
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(pending, Some(PendingMint {
+            owner: "buyer".to_string(),
+            quantity: 1,
+            payment: coin(100, "earth"),
+            beacon: "beacon".to_string(),
+            requested_at: mock_env().block.time.seconds(),
+            quota: MintQuota::Public,
+        }));
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), callback("mint-1", 7))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("beacon", &[]), callback("mint-1", 7))
+            .unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(res.messages[1].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "admin".to_string(),
+            amount: coins(100, "earth"),
+        }));
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.mint_count, Uint128::new(1));
+        let remaining: u32 =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GetMintableCount {}).unwrap()).unwrap();
+        assert_eq!(remaining, 2);
+
+        // a job is only fulfilled once
+        execute(deps.as_mut(), mock_env(), mock_info("beacon", &[]), callback("mint-1", 7)).unwrap_err();
+    }
 
-    let mut rng = Prng::new(seed, &rng_entropy);
+    #[test]
+    fn pool_draws_every_id_once() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), 50);
+        let mut rng = Prng::new(b"seed", b"pool");
+        let mut drawn = vec![];
+        for remaining in (1..=50u32).rev() {
+            let slot = rng.gen_index(remaining as usize) as u32;
+            drawn.push(take_mintable_id(deps.as_mut().storage, slot).unwrap());
+        }
+        assert_eq!(MINTABLE_COUNT.load(&deps.storage).unwrap(), 0);
+        assert_ne!(drawn, (1..=50).collect::<Vec<_>>());
+        drawn.sort_unstable();
+        assert_eq!(drawn, (1..=50).collect::<Vec<_>>());
+        let left = MINTABLE_IDS.range(&deps.storage, None, None, cosmwasm_std::Order::Ascending);
+        assert_eq!(left.count(), 0);
+    }
 
-    rng.rand_bytes()
-}
+    #[test]
+    fn mint_several_tokens() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), 10);
+        CW721_ADDRESS.save(deps.as_mut().storage, &"collection".to_string()).unwrap();
+
+        // callers from before quantities mint one token
+        let msg: ExecuteMsg = from_binary(&Binary::from(br#"{"mint":{}}"#.to_vec())).unwrap();
+        assert_eq!(msg, ExecuteMsg::Mint { quantity: 1, proof: None });
+
+        let mint = |quantity| ExecuteMsg::Mint { quantity, proof: None };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), mint(0)).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMintQuantity { max: 3 }));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(400, "earth")), mint(4))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMintQuantity { max: 3 }));
+        // the price is per token
+        let err = execute(deps.as_mut(), mock_env(), mock_info("other", &coins(100, "earth")), mint(3))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NotExactFunds { .. }));
+
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(300, "earth")), mint(3)).unwrap();
+        let res = execute(deps.as_mut(), mock_env(), mock_info("beacon", &[]), callback("mint-1", 9))
+            .unwrap();
+        assert_eq!(res.messages.len(), 4);
+        assert_eq!(MINTABLE_COUNT.load(&deps.storage).unwrap(), 7);
+
+        // 3 of 5 used, so the whole quantity no longer fits the wallet limit
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(300, "earth")), mint(3))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::MaxPerAddressLimitExceeded {}));
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(200, "earth")), mint(2)).unwrap();
+        assert_eq!(query_user_info(deps.as_ref(), "buyer".to_string()).unwrap(), Uint128::new(5));
+    }
 
+    #[test]
+    fn mint_price_overflow_is_an_error() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), 10);
+        CONFIG
+            .update(deps.as_mut().storage, |mut state| -> StdResult<_> {
+                state.public_price.amount = Uint128::MAX;
+                Ok(state)
+            })
+            .unwrap();
+
+        let msg = ExecuteMsg::Mint { quantity: 2, proof: None };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::Overflow { .. })));
+    }
+
+    #[test]
+    fn mint_in_phases() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), 10);
+        let start = mock_env().block.time.seconds();
+        let phase = |name: &str, start_time, end_time, price, allowlist_id| MintPhase {
+            name: name.to_string(),
+            start_time,
+            end_time,
+            price: coin(price, "earth"),
+            per_address_limit: Uint128::new(2),
+            allowlist_id,
+            supply_cap: Some(Uint128::new(3)),
+        };
+        let og = phase("og", start, Some(start + 100), 50, Some(1));
+        let public = phase("public", start + 100, None, 100, None);
+
+        let overlapping = vec![og.clone(), phase("public", start + 50, None, 100, None)];
+        let msg = ExecuteMsg::SetPhases { phases: overlapping };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidPhases {}));
+        let msg = ExecuteMsg::SetPhases { phases: vec![og.clone(), public.clone()] };
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap();
+        let msg = ExecuteMsg::AddAllowlistUsers { allowlist_id: 1, addresses: vec!["og1".to_string(), "og2".to_string()] };
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap();
+
+        let active: Option<PhaseResponse> =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GetActivePhase {}).unwrap()).unwrap();
+        assert_eq!(active, Some(PhaseResponse { id: 0, phase: og, minted: Uint128::zero() }));
+
+        let mint = |quantity| ExecuteMsg::Mint { quantity, proof: None };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &coins(50, "earth")), mint(1))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NotWhiteListedUser {}));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("og1", &coins(150, "earth")), mint(3))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::MaxPerAddressLimitExceeded {}));
+        execute(deps.as_mut(), mock_env(), mock_info("og1", &coins(100, "earth")), mint(2)).unwrap();
+        // the phase cap is 3
+        let err = execute(deps.as_mut(), mock_env(), mock_info("og2", &coins(100, "earth")), mint(2))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::SoldOut {}));
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(100);
+        let active: Option<PhaseResponse> =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::GetActivePhase {}).unwrap()).unwrap();
+        assert_eq!(active.unwrap().phase, public);
+        // a new phase has its own per address limit and price
+        let err = execute(deps.as_mut(), env.clone(), mock_info("og1", &coins(50, "earth")), mint(1))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NotExactFunds { .. }));
+        execute(deps.as_mut(), env, mock_info("anyone", &coins(200, "earth")), mint(2)).unwrap();
+    }
+
+    #[test]
+    fn phase_counts_survive_replacing_phases() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), 10);
+        let start = mock_env().block.time.seconds();
+        let phase = |name: &str| MintPhase {
+            name: name.to_string(),
+            start_time: start,
+            end_time: None,
+            price: coin(50, "earth"),
+            per_address_limit: Uint128::new(2),
+            allowlist_id: None,
+            supply_cap: Some(Uint128::new(3)),
+        };
+        let set_phases = |deps: DepsMut, phases| {
+            execute(deps, mock_env(), mock_info("admin", &[]), ExecuteMsg::SetPhases { phases })
+        };
+        let mint = ExecuteMsg::Mint { quantity: 2, proof: None };
+
+        set_phases(deps.as_mut(), vec![phase("presale")]).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(100, "earth")), mint.clone()).unwrap();
+
+        // the new phase at the same index starts from zero
+        set_phases(deps.as_mut(), vec![phase("public")]).unwrap();
+        let active: Option<PhaseResponse> =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::GetActivePhase {}).unwrap()).unwrap();
+        assert_eq!(active.unwrap().minted, Uint128::zero());
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(100, "earth")), mint.clone()).unwrap();
+
+        // putting the old phase back keeps its wallet limit and cap used
+        set_phases(deps.as_mut(), vec![phase("presale")]).unwrap();
+        let one = ExecuteMsg::Mint { quantity: 1, proof: None };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(50, "earth")), one)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::MaxPerAddressLimitExceeded {}));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("other", &coins(100, "earth")), mint)
+            .unwrap_err();
+        assert!(matches!(err, ContractError::SoldOut {}));
+
+        let mut second = phase("presale");
+        second.start_time = start + 100;
+        let mut first = phase("presale");
+        first.end_time = Some(start + 100);
+        let err = set_phases(deps.as_mut(), vec![first, second]).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidPhases {}));
+    }
+
+    #[test]
+    fn mint_with_merkle_proof() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), 10);
+        let start = mock_env().block.time.seconds();
+        let phase = MintPhase {
+            name: "allowlist".to_string(),
+            start_time: start,
+            end_time: None,
+            price: coin(50, "earth"),
+            per_address_limit: Uint128::new(5),
+            allowlist_id: Some(7),
+            supply_cap: None,
+        };
+        let msg = ExecuteMsg::SetPhases { phases: vec![phase] };
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap();
+
+        let alice = leaf_hash("alice", Uint128::new(2));
+        let bob = leaf_hash("bob", Uint128::new(1));
+        let root = Binary::from(crate::merkle::node_hash(&alice, &bob));
+        let msg = ExecuteMsg::SetMerkleRoot { allowlist_id: 7, root: Some(Binary::from(vec![1u8; 31])) };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMerkleRoot {}));
+        let msg = ExecuteMsg::SetMerkleRoot { allowlist_id: 7, root: Some(root) };
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap();
+
+        let proof = |limit| AllowlistProof { limit: Uint128::new(limit), proof: vec![Binary::from(bob)] };
+        let verify = |deps: Deps, limit| -> AllowlistProofResponse {
+            let msg = QueryMsg::VerifyAllowlistProof {
+                allowlist_id: 7,
+                address: "alice".to_string(),
+                proof: proof(limit),
+            };
+            from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap()
+        };
+        assert!(verify(deps.as_ref(), 2).valid);
+        assert!(!verify(deps.as_ref(), 3).valid);
+
+        let mint = |quantity, limit| ExecuteMsg::Mint { quantity, proof: Some(proof(limit)) };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &coins(150, "earth")), mint(3, 3))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMerkleProof {}));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &coins(50, "earth")), ExecuteMsg::Mint { quantity: 1, proof: None })
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NotWhiteListedUser {}));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &coins(150, "earth")), mint(3, 2))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::MaxPerAddressLimitExceeded {}));
+
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &coins(100, "earth")), mint(2, 2)).unwrap();
+        assert_eq!(verify(deps.as_ref(), 2).minted, Uint128::new(2));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &coins(50, "earth")), mint(1, 2))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::MaxPerAddressLimitExceeded {}));
+    }
+
+    #[test]
+    fn mint_needs_a_beacon() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), 3);
+        BEACON.remove(deps.as_mut().storage);
+
+        let info = mock_info("buyer", &coins(100, "earth"));
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), ExecuteMsg::Mint { quantity: 1, proof: None }).unwrap_err();
+        assert!(matches!(err, ContractError::NoRandomnessBeacon {}));
+
+        let msg = ExecuteMsg::SetRandomnessBeacon { address: "beacon".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap();
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Mint { quantity: 1, proof: None }).unwrap();
+    }
+
+    #[test]
+    fn stranded_mint_is_retried_or_refunded() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), 10);
+        CW721_ADDRESS.save(deps.as_mut().storage, &"collection".to_string()).unwrap();
+        let mint = |quantity| ExecuteMsg::Mint { quantity, proof: None };
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &coins(100, "earth")), mint(1)).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("buyer2", &coins(200, "earth")), mint(2)).unwrap();
+
+        // the admin can not swap in another beacon while jobs wait
+        let msg = ExecuteMsg::SetRandomnessBeacon { address: "beacon2".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::BeaconAlreadySet {}));
+
+        let retry = ExecuteMsg::RetryPendingMint { job_id: "mint-1".to_string() };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), retry.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::PendingMintNotExpired { timeout: PENDING_MINT_TIMEOUT }));
+        let mut later = mock_env();
+        later.block.time = later.block.time.plus_seconds(PENDING_MINT_TIMEOUT);
+        let err = execute(deps.as_mut(), later.clone(), mock_info("other", &[]), retry.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // a retry goes to the beacon of the job
+        let res = execute(deps.as_mut(), later.clone(), mock_info("buyer", &[]), retry.clone()).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "beacon".to_string(),
+            msg: to_binary(&BeaconExecuteMsg::GetNextRandomness { job_id: "mint-1".to_string() }).unwrap(),
+            funds: vec![],
+        }));
+        execute(deps.as_mut(), later.clone(), mock_info("beacon", &[]), callback("mint-1", 7)).unwrap();
+        execute(deps.as_mut(), later.clone(), mock_info("admin", &[]), retry).unwrap_err();
+
+        // the other job is cancelled: refund, and the tokens and wallet limit are free again
+        let cancel = ExecuteMsg::CancelPendingMint { job_id: "mint-2".to_string() };
+        let res = execute(deps.as_mut(), later.clone(), mock_info("buyer2", &[]), cancel.clone()).unwrap();
+        assert_eq!(res.messages[0].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "buyer2".to_string(),
+            amount: coins(200, "earth"),
+        }));
+        assert_eq!(query_state_info(deps.as_ref()).unwrap().mint_count, Uint128::new(1));
+        assert_eq!(query_user_info(deps.as_ref(), "buyer2".to_string()).unwrap(), Uint128::zero());
+        let err = execute(deps.as_mut(), later.clone(), mock_info("beacon", &[]), callback("mint-2", 7))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::NotFound { .. })));
+        execute(deps.as_mut(), later.clone(), mock_info("buyer2", &[]), cancel).unwrap_err();
+
+        // a cancelled phase mint gives back the phase cap
+        let phase = MintPhase {
+            name: "public".to_string(),
+            start_time: 0,
+            end_time: None,
+            price: coin(100, "earth"),
+            per_address_limit: Uint128::new(2),
+            allowlist_id: None,
+            supply_cap: Some(Uint128::new(2)),
+        };
+        let msg = ExecuteMsg::SetPhases { phases: vec![phase] };
+        execute(deps.as_mut(), later.clone(), mock_info("admin", &[]), msg).unwrap();
+        execute(deps.as_mut(), later.clone(), mock_info("buyer3", &coins(200, "earth")), mint(2)).unwrap();
+        let err = execute(deps.as_mut(), later.clone(), mock_info("buyer4", &coins(100, "earth")), mint(1))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::SoldOut {}));
+        let mut much_later = later;
+        much_later.block.time = much_later.block.time.plus_seconds(PENDING_MINT_TIMEOUT);
+        let cancel = ExecuteMsg::CancelPendingMint { job_id: "mint-3".to_string() };
+        execute(deps.as_mut(), much_later.clone(), mock_info("admin", &[]), cancel).unwrap();
+        execute(deps.as_mut(), much_later, mock_info("buyer5", &coins(200, "earth")), mint(2)).unwrap();
+    }
+}
@@ -49,4 +49,31 @@ pub enum ContractError {
     #[error("InvalidStartTime {0} < {1}")]
     InvalidStartTime(u64, u64),
 
+    #[error("Mint quantity must be between 1 and {max}")]
+    InvalidMintQuantity { max: u32 },
+
+    #[error("Phases must have unique names, be in time order, not overlap, and only the last may be open ended")]
+    InvalidPhases {},
+
+    #[error("No mint phase is active")]
+    NoActivePhase {},
+
+    #[error("Merkle root must be 32 bytes")]
+    InvalidMerkleRoot {},
+
+    #[error("Invalid allowlist proof")]
+    InvalidMerkleProof {},
+
+    #[error("No randomness beacon is set")]
+    NoRandomnessBeacon {},
+
+    #[error("The randomness beacon is already set")]
+    BeaconAlreadySet {},
+
+    #[error("Pending mint can be retried or cancelled after {timeout} seconds")]
+    PendingMintNotExpired { timeout: u64 },
+
+    #[error("Randomness must be 32 bytes")]
+    InvalidRandomness {},
+
 }
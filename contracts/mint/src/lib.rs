@@ -3,3 +3,4 @@ mod error;
 pub mod msg;
 pub mod state;
 pub mod rand;
+pub mod merkle;
@@ -0,0 +1,65 @@
+use cosmwasm_std::{Binary, Uint128};
+
+use crate::rand::sha_256;
+
+/// Leaves and inner nodes are hashed with different prefixes, so an inner node
+/// can never be passed off as a leaf (second preimage).
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Leaf of an allowlist tree: `sha256(0x00 ++ address ++ limit)`, with the
+/// address as its UTF-8 bytes and the limit as 16 big-endian bytes.
+pub fn leaf_hash(address: &str, limit: Uint128) -> [u8; 32] {
+    let mut data = vec![LEAF_PREFIX];
+    data.extend_from_slice(address.as_bytes());
+    data.extend_from_slice(&limit.u128().to_be_bytes());
+    sha_256(&data)
+}
+
+/// Inner node of an allowlist tree: `sha256(0x01 ++ lower ++ higher)` of its two
+/// children, the smaller hash first.
+pub fn node_hash(a: &[u8], b: &[u8]) -> [u8; 32] {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    sha_256(&[&[NODE_PREFIX], first, second].concat())
+}
+
+/// Hashes `leaf` up the tree with the sibling hashes in `proof` and compares the
+/// result with `root`.
+pub fn verify_proof(root: &[u8], leaf: [u8; 32], proof: &[Binary]) -> bool {
+    let mut hash = leaf;
+    for sibling in proof {
+        if sibling.len() != 32 {
+            return false;
+        }
+        hash = node_hash(&hash, sibling);
+    }
+    hash.as_slice() == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_proof() {
+        let alice = leaf_hash("alice", Uint128::new(2));
+        let bob = leaf_hash("bob", Uint128::new(1));
+        let carol = leaf_hash("carol", Uint128::new(5));
+        let root = node_hash(&node_hash(&alice, &bob), &carol);
+
+        let proof = vec![Binary::from(bob), Binary::from(carol)];
+        assert!(verify_proof(&root, alice, &proof));
+        let proof = vec![Binary::from(node_hash(&alice, &bob))];
+        assert!(verify_proof(&root, carol, &proof));
+
+        // a different limit is a different leaf
+        let proof = vec![Binary::from(bob), Binary::from(carol)];
+        assert!(!verify_proof(&root, leaf_hash("alice", Uint128::new(3)), &proof));
+        assert!(!verify_proof(&root, alice, &[Binary::from(vec![0u8; 31])]));
+        assert!(!verify_proof(&root, alice, &[]));
+
+        // leaves and nodes are hashed apart, the raw pair hash is no node
+        let unprefixed = sha_256(&[alice, bob].concat());
+        assert_ne!(node_hash(&alice, &bob), unprefixed);
+    }
+}
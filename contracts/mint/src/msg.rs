@@ -3,32 +3,114 @@ use cw_utils::Expiration;
 use schemars::{JsonSchema};
 use serde::{Deserialize, Serialize};
 
-use crate::state::{Cw721InitMessage, State};
+use crate::state::{Cw721InitMessage, MintPhase, State};
 
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
     pub cw721_instantiate_msg: Cw721InitMessage,
     pub cw721_code_id: u64,
-    pub content_type: String
+    pub content_type: String,
+    /// Contract drawing the token ids. If not given, it can be set once later with
+    /// `SetRandomnessBeacon`.
+    pub randomness_beacon: Option<String>
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
-    Mint{},
-    SetConfig{config:State},
+    /// Pays for tokens whose ids are drawn when the randomness beacon calls back.
+    /// `quantity` defaults to one. `proof` is needed when the active phase uses a
+    /// merkle allowlist.
+    Mint{
+        #[serde(default = "default_quantity")]
+        quantity:u32,
+        proof:Option<AllowlistProof>
+    },
+    /// only randomness beacon. Mints the pending tokens of `callback.job_id`.
+    ReceiveRandomness{callback:RandomnessCallback},
+    SetConfig{config:Box<State>},
     SetMintFlag{flag:bool},
+    /// only admin, and only when no beacon was given at instantiate. The beacon
+    /// can not be changed afterwards.
+    SetRandomnessBeacon{address:String},
+    /// only minter or admin, once the mint has waited an hour. Asks the beacon of
+    /// the job again for its randomness.
+    RetryPendingMint{job_id:String},
+    /// only minter or admin, once the mint has waited an hour. Refunds the payment
+    /// and gives back the reserved tokens and mint limits.
+    CancelPendingMint{job_id:String},
     AddWhiteUsers{white_users:Vec<WhiteUserInfo>},
+    /// Replaces the sale phases. Mint counts stay with the phase name.
+    SetPhases{phases:Vec<MintPhase>},
+    AddAllowlistUsers{allowlist_id:u32, addresses:Vec<String>},
+    RemoveAllowlistUsers{allowlist_id:u32, addresses:Vec<String>},
+    /// Sets or clears the merkle root of an allowlist.
+    SetMerkleRoot{allowlist_id:u32, root:Option<Binary>},
     ChangeSaleType{is_public_mint:bool}
 }
 
+fn default_quantity() -> u32 {
+    1
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
       GetStateInfo{},
       GetUserInfo{ address:String },
-      GetCollectionAddress{ }
+      GetCollectionAddress{ },
+      /// Mint still waiting for randomness. Return type: Option<PendingMint>.
+      GetPendingMint{ job_id:String },
+      /// Number of token ids not drawn yet. Return type: u32.
+      GetMintableCount{ },
+      /// Return type: Option<PhaseResponse>.
+      GetActivePhase{ },
+      /// Checks a proof without minting. A leaf is
+      /// `sha256(0x00 ++ address ++ limit)`, with the address as its UTF-8 bytes
+      /// and the limit as 16 big-endian bytes. An inner node is
+      /// `sha256(0x01 ++ lower ++ higher)` of its two children, the smaller hash
+      /// first. Return type: AllowlistProofResponse.
+      VerifyAllowlistProof{ allowlist_id:u32, address:String, proof:AllowlistProof }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PhaseResponse {
+    /// index of the phase in the configured list
+    pub id: u32,
+    pub phase: MintPhase,
+    pub minted: Uint128,
+}
+
+/// Membership of `(sender, limit)` in a merkle allowlist.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AllowlistProof {
+    pub limit: Uint128,
+    /// sibling hashes from the leaf up to the root
+    pub proof: Vec<Binary>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AllowlistProofResponse {
+    pub valid: bool,
+    pub limit: Uint128,
+    pub minted: Uint128,
+}
+
+/// Randomness published by the beacon for a job requested with `GetNextRandomness`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RandomnessCallback {
+    pub job_id: String,
+    /// 32 bytes of randomness
+    pub randomness: Binary,
+}
+
+/// Request sent to the randomness beacon, which answers with a
+/// `ExecuteMsg::ReceiveRandomness` callback once the randomness is published.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BeaconExecuteMsg {
+    GetNextRandomness { job_id: String },
 }
 
 
@@ -22,8 +22,8 @@ impl Prng {
         let mut hasher = Sha256::new();
 
         // write input message
-        hasher.update(&seed);
-        hasher.update(&entropy);
+        hasher.update(seed);
+        hasher.update(entropy);
         let hash = hasher.finalize();
 
         let mut hash_bytes = [0u8; 32];
@@ -40,6 +40,23 @@ impl Prng {
 
         bytes
     }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    /// Uniform index below `len`, which must not be zero. Draws falling in the
+    /// incomplete last window of `u32` values are rejected to avoid modulo bias.
+    pub fn gen_index(&mut self, len: usize) -> usize {
+        let len = len as u32;
+        let zone = u32::MAX - u32::MAX % len;
+        loop {
+            let value = self.next_u32();
+            if value < zone {
+                return (value % len) as usize;
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -72,4 +89,17 @@ mod tests {
         assert_eq!(r3, rng.rand_bytes());
         assert_eq!(r4, rng.rand_bytes());
     }
+
+    #[test]
+    fn test_gen_index() {
+        let mut rng = Prng::new(b"foo", b"bar!");
+        let mut seen = [false; 5];
+        for _ in 0..100 {
+            let index = rng.gen_index(5);
+            assert!(index < 5);
+            seen[index] = true;
+        }
+        assert!(seen.iter().all(|seen| *seen));
+        assert_eq!(rng.gen_index(1), 0);
+    }
 }
@@ -1,4 +1,4 @@
-use cosmwasm_std::{Uint128, Decimal,Coin};
+use cosmwasm_std::{Uint128, Decimal,Coin, StdResult, Storage, Binary};
 use cw_storage_plus::{Map,Item};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -8,6 +8,42 @@ pub const USERINFO : Map<&str, Uint128> = Map::new("config_user_info");
 pub const WHITEUSERS : Map<&str,Uint128>  = Map::new("config_white_user_info");
 pub const CW721_ADDRESS : Item<String> = Item::new("config_collection_address");
 pub const MIDDLEWARE: Item<String> = Item::new("config_middleware");
+/// Set once, so the admin can not swap in a beacon that picks the ids.
+pub const BEACON: Item<String> = Item::new("config_beacon");
+pub const MINT_JOB_COUNT: Item<u64> = Item::new("config_mint_job_count");
+/// Paid mints waiting for the beacon, keyed by job id. The payment stays in the
+/// contract until the tokens are minted.
+pub const PENDING_MINTS: Map<&str, PendingMint> = Map::new("config_pending_mints");
+/// Token ids not drawn yet, as a sparse array: slot `i` holds id `i + 1` until it
+/// is overwritten by a draw. Only the first `MINTABLE_COUNT` slots are live.
+pub const MINTABLE_IDS: Map<u32, u32> = Map::new("config_mintable_ids");
+pub const MINTABLE_COUNT: Item<u32> = Item::new("config_mintable_count");
+/// Sale phases in time order. When empty, `State.is_public_mint` and the two
+/// prices apply instead.
+pub const PHASES: Item<Vec<MintPhase>> = Item::new("config_phases");
+/// Tokens minted in each phase, keyed by its name so the counts stay with the
+/// phase when `PHASES` is replaced.
+pub const PHASE_MINTED: Map<&str, Uint128> = Map::new("config_phase_minted");
+pub const PHASE_USER_MINTS: Map<(&str, &str), Uint128> = Map::new("config_phase_user_mints");
+pub const ALLOWLISTS: Map<(u32, &str), bool> = Map::new("config_allowlists");
+/// Merkle roots of allowlists too large to store address by address.
+pub const MERKLE_ROOTS: Map<u32, Binary> = Map::new("config_merkle_roots");
+/// Tokens minted with a merkle proof, counted against the limit in the leaf.
+pub const MERKLE_MINTS: Map<(u32, &str), Uint128> = Map::new("config_merkle_mints");
+
+/// Removes the id in `slot` from the pool by moving the last live id into its
+/// place (Fisher–Yates), so each draw touches two slots whatever the supply.
+pub fn take_mintable_id(storage: &mut dyn Storage, slot: u32) -> StdResult<u32> {
+    let last = MINTABLE_COUNT.load(storage)? - 1;
+    let id = MINTABLE_IDS.may_load(storage, slot)?.unwrap_or(slot + 1);
+    if slot != last {
+        let last_id = MINTABLE_IDS.may_load(storage, last)?.unwrap_or(last + 1);
+        MINTABLE_IDS.save(storage, slot, &last_id)?;
+    }
+    MINTABLE_IDS.remove(storage, last);
+    MINTABLE_COUNT.save(storage, &last)?;
+    Ok(id)
+}
 
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -18,10 +54,11 @@ pub struct State {
     pub mint_count: Uint128,
     pub start_mint_time: u64,
     pub per_address_limit: Uint128,
+    /// most tokens a single `Mint` may ask for
+    pub max_per_tx: u32,
     pub public_price: Coin,
     pub private_price: Coin,
     pub mint_flag: bool,
-    pub enable_token_id: Option<Vec<u32>>,
     pub is_public_mint: bool,
     pub nft_base_name: String,
     pub base_image_uri: String,
@@ -29,6 +66,55 @@ pub struct State {
     pub content_type: String
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MintPhase {
+    /// unique, the mint counts of the phase are kept under it
+    pub name: String,
+    pub start_time: u64,
+    /// open until the end of the sale when not set
+    pub end_time: Option<u64>,
+    pub price: Coin,
+    pub per_address_limit: Uint128,
+    /// only members of this allowlist may mint in the phase, either added with
+    /// `AddAllowlistUsers` or proven against its merkle root
+    pub allowlist_id: Option<u32>,
+    /// most tokens minted in the phase
+    pub supply_cap: Option<Uint128>,
+}
+
+impl MintPhase {
+    pub fn is_active(&self, time: u64) -> bool {
+        self.start_time <= time && self.end_time.is_none_or(|end| time < end)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingMint {
+    pub owner: String,
+    pub quantity: u32,
+    /// held until the beacon answers, refunded if the mint is cancelled
+    pub payment: Coin,
+    /// beacon asked for the randomness, the only one a retry goes to
+    pub beacon: String,
+    /// block time of the request, in seconds
+    pub requested_at: u64,
+    pub quota: MintQuota,
+}
+
+/// Per address or per phase count a pending mint was charged against, released
+/// again if the mint is cancelled.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MintQuota {
+    Public,
+    WhiteUser,
+    Phase {
+        name: String,
+        /// set when the mint was proven against this merkle allowlist
+        merkle_allowlist: Option<u32>,
+    },
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct AdminInfo {
     pub address:String,
@@ -69,6 +155,9 @@ pub struct MintInfo {
     pub total_supply: Uint128,
     pub start_mint_time: u64,
     pub per_address_limit: Uint128,
+    /// defaults to the per address limit
+    pub max_per_tx: Option<u32>,
+    pub phases: Option<Vec<MintPhase>>,
     pub public_price: Coin,
     pub private_price:Coin,
     pub mint_flag:bool,
@@ -0,0 +1,69 @@
+use cosmwasm_std::{
+    entry_point, to_binary, Binary, DepsMut, Env, MessageInfo, Response, StdResult, WasmMsg,
+};
+use sha2::{Digest, Sha256};
+
+use crate::msg::{ExecuteMsg, InstantiateMsg, RandomnessCallback, ReceiverExecuteMsg};
+
+#[entry_point]
+pub fn instantiate(
+    _deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    _msg: InstantiateMsg,
+) -> StdResult<Response> {
+    Ok(Response::new().add_attribute("action", "instantiate"))
+}
+
+/// Answers every request right away with randomness derived from the job id and
+/// the block. This is predictable and only meant for tests and local networks.
+#[entry_point]
+pub fn execute(_deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> StdResult<Response> {
+    match msg {
+        ExecuteMsg::GetNextRandomness { job_id } => {
+            let randomness = Sha256::new()
+                .chain(job_id.as_bytes())
+                .chain(env.block.height.to_be_bytes())
+                .chain(env.block.time.nanos().to_be_bytes())
+                .finalize();
+            let callback = ReceiverExecuteMsg::ReceiveRandomness {
+                callback: RandomnessCallback {
+                    job_id: job_id.clone(),
+                    randomness: Binary::from(randomness.as_slice()),
+                },
+            };
+            Ok(Response::new()
+                .add_message(WasmMsg::Execute {
+                    contract_addr: info.sender.to_string(),
+                    msg: to_binary(&callback)?,
+                    funds: vec![],
+                })
+                .add_attribute("action", "get_next_randomness")
+                .add_attribute("job_id", job_id))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{from_binary, CosmosMsg};
+
+    #[test]
+    fn calls_back_the_requester() {
+        let mut deps = mock_dependencies();
+        let msg = ExecuteMsg::GetNextRandomness { job_id: "mint-1".to_string() };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("minter", &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, msg, .. }) => {
+                assert_eq!(contract_addr, "minter");
+                let ReceiverExecuteMsg::ReceiveRandomness { callback } = from_binary(msg).unwrap();
+                assert_eq!(callback.job_id, "mint-1");
+                assert_eq!(callback.randomness.len(), 32);
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+}
@@ -5,9 +5,9 @@ use cosmwasm_schema::{export_schema, export_schema_with_title, remove_schemas, s
 use cosmwasm_std::Empty;
 
 use cw721::{
-    AllNftInfoResponse, ApprovalResponse, ApprovalsResponse, ContractInfoResponse, Cw721ExecuteMsg,
-    Cw721QueryMsg, Cw721ReceiveMsg, NftInfoResponse, NumTokensResponse, OperatorsResponse,
-    OwnerOfResponse, TokensResponse,
+    AllNftInfoResponse, ApprovalResponse, ApprovalsResponse, CheckRoyaltiesResponse,
+    ContractInfoResponse, Cw721ExecuteMsg, Cw721QueryMsg, Cw721ReceiveMsg, NftInfoResponse,
+    NumTokensResponse, OperatorsResponse, OwnerOfResponse, RoyaltiesInfoResponse, TokensResponse,
 };
 
 type Extension = Option<Empty>;
@@ -38,4 +38,6 @@ fn main() {
     );
     export_schema(&schema_for!(NumTokensResponse), &out_dir);
     export_schema_with_title(&schema_for!(TokensResponse<Extension>), &out_dir,"TokensResponse");
+    export_schema(&schema_for!(RoyaltiesInfoResponse), &out_dir);
+    export_schema(&schema_for!(CheckRoyaltiesResponse), &out_dir);
 }
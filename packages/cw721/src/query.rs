@@ -63,7 +63,18 @@ pub enum Cw721QueryMsg {
         start_after: Option<String>,
         limit: Option<u32>,
     },
-   
+
+    /// With Royalty extension (EIP-2981 style).
+    /// Returns the royalty owed when `token_id` is sold for `sale_price`.
+    /// Return type: `RoyaltiesInfoResponse`
+    RoyaltyInfo {
+        token_id: String,
+        sale_price: Uint128,
+    },
+    /// With Royalty extension.
+    /// Lets a marketplace discover whether this contract pays royalties.
+    /// Return type: `CheckRoyaltiesResponse`
+    CheckRoyalties {},
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -116,6 +127,20 @@ pub struct CollectionInfoResponse {
   pub royalty_info : Royalty
 }
 
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct RoyaltiesInfoResponse {
+    /// Address the royalty should be paid to
+    pub address: String,
+    /// Royalty owed on the given sale price, in the sale denom
+    pub royalty_amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct CheckRoyaltiesResponse {
+    /// True if `RoyaltyInfo` can return a non-zero royalty
+    pub royalty_payments: bool,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 pub struct NftInfoResponse<T> {
     /// Universal resource identifier for this NFT
@@ -4,10 +4,11 @@ use serde::Serialize;
 
 use crate::query::ApprovalResponse;
 use crate::{
-    AllNftInfoResponse, ApprovalsResponse, ContractInfoResponse, NftInfoResponse,
-    NumTokensResponse, OperatorsResponse, OwnerOfResponse, TokensResponse,TokensInfo,CollectionInfoResponse,Royalty
+    AllNftInfoResponse, ApprovalsResponse, CheckRoyaltiesResponse, ContractInfoResponse,
+    NftInfoResponse, NumTokensResponse, OperatorsResponse, OwnerOfResponse,
+    RoyaltiesInfoResponse, TokensResponse,TokensInfo,CollectionInfoResponse,Royalty
 };
-use cosmwasm_std::{Binary, Deps, DepsMut, Empty, Env, MessageInfo, Response, StdResult};
+use cosmwasm_std::{Binary, Deps, DepsMut, Empty, Env, MessageInfo, Response, StdResult, Uint128};
 use cw_utils::Expiration;
 
 // TODO: move this somewhere else... ideally cosmwasm-std
@@ -158,4 +159,13 @@ where
         token_id: String,
         include_expired: bool,
     ) -> StdResult<AllNftInfoResponse<T>>;
+
+    fn royalty_info(
+        &self,
+        deps: Deps,
+        token_id: String,
+        sale_price: Uint128,
+    ) -> StdResult<RoyaltiesInfoResponse>;
+
+    fn check_royalties(&self, deps: Deps) -> StdResult<CheckRoyaltiesResponse>;
 }